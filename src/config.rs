@@ -1,11 +1,14 @@
 use std::collections::HashMap;
 use std::env::current_dir;
-use std::fs::{File, OpenOptions};
-use std::path::{Path, PathBuf};
+use std::fs::{self, File, OpenOptions};
+use std::path::Path;
 
 use serde::{Deserialize, Serialize};
 
-use crate::rustgen_error::RustgenError;
+use crate::rustgen_error::{ErrorKind, RustgenError, RustgenResult};
+use crate::template::ConflictPolicy;
+
+const RC_FILE_NAMES: [&str; 2] = [".rustgenrc.yaml", ".rustgenrc.yml"];
 
 /// Available application config
 ///
@@ -15,11 +18,13 @@ use crate::rustgen_error::RustgenError;
 /// | --- | --- | --- |
 /// | `template_path` | Path to the templates root directory. Containing structure has to match "_generator/TYPE/ACTION/template.hbs" | `_generator` |
 /// | `defaults` | Default variables set in templates (e.g. the name of your *main* plugin). | *None* |
+/// | `on_conflict` | What to do if a file which should be created exists (`fail`, `skip`, `overwrite`, `prompt` or `new`), can be overwritten per template header | `fail` |
 ///
 /// # Example
 ///
 /// ```yaml
 /// template_path: ".generator"
+/// on_conflict: prompt
 /// defaults:
 ///     basepath: "./plugin/MyPlugin"
 /// ```
@@ -29,35 +34,75 @@ pub struct ApplicationConfig {
     pub template_path: String,
     #[serde(default)]
     pub default: HashMap<String, String>,
+    #[serde(default)]
+    pub on_conflict: ConflictPolicy,
 }
 
 /// Reads the Application config from the paths (in the following order)
 /// - ./.rustgenrc.yaml
 /// - ./.rustgenrc.yml
 ///
-pub fn read() -> ApplicationConfig {
-    let file = open_rc_file(".rustgenrc.yaml").or(open_rc_file(".rustgenrc.yml"));
+/// Falls back to the default config if no file exists, but fails if the file is invalid.
+///
+pub fn read() -> RustgenResult<ApplicationConfig> {
+    let file = open_rc_file(RC_FILE_NAMES[0]).or_else(|_| open_rc_file(RC_FILE_NAMES[1]));
+
+    match file {
+        Ok(file) => serde_yaml::from_reader(file).map_err(|error| {
+            RustgenError::new(
+                ErrorKind::Config,
+                format!("rustgenrc.yaml syntax is invalid: {}", error),
+            )
+            .with_source(error)
+        }),
+        Err(_) => Ok(ApplicationConfig::default()),
+    }
+}
+
+/// Creates a default .rustgenrc.yml and the template directory (if they do not exist yet)
+pub fn init() -> RustgenResult<()> {
+    let cwd = current_dir()?;
+
+    if RC_FILE_NAMES.iter().any(|name| cwd.join(name).exists()) {
+        println!("Config file already exists, skipping");
+    } else {
+        let config = serde_yaml::to_string(&ApplicationConfig::default()).map_err(|error| {
+            RustgenError::new(ErrorKind::Config, "Could not serialize the default config")
+                .with_source(error)
+        })?;
+
+        fs::write(cwd.join(RC_FILE_NAMES[1]), config)?;
+        println!("Created {}", RC_FILE_NAMES[1]);
+    }
 
-    if let Ok(file) = file {
-        return serde_yaml::from_reader(file).unwrap_or_else(|error| {
-            eprintln!("rustgenrc.yaml syntax is invalid: {:?}", error);
+    let config = read()?;
+    let template_path = cwd.join(&config.template_path);
 
-            ApplicationConfig::default()
-        });
+    if template_path.exists() {
+        println!(
+            "Template directory {} already exists, skipping",
+            config.template_path
+        );
+    } else {
+        fs::create_dir_all(template_path)?;
+        println!("Created template directory {}", config.template_path);
     }
 
-    ApplicationConfig::default()
+    Ok(())
 }
 
 fn open_rc_file<P: AsRef<Path>>(path: P) -> Result<File, RustgenError> {
-    let current_dir = current_dir().unwrap_or(PathBuf::new());
+    let current_dir = current_dir().unwrap_or_default();
 
     OpenOptions::new()
         .read(true)
         .write(false)
         .create(false)
         .open(current_dir.join(path))
-        .or(Err(RustgenError::new("Could not find .rustgenrc.yaml")))
+        .map_err(|error| {
+            RustgenError::new(ErrorKind::Config, "Could not find .rustgenrc.yaml")
+                .with_source(error)
+        })
 }
 
 fn default_template_path() -> String {
@@ -69,6 +114,7 @@ impl Default for ApplicationConfig {
         ApplicationConfig {
             template_path: String::from("_generator"),
             default: Default::default(),
+            on_conflict: Default::default(),
         }
     }
 }
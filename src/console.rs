@@ -1,40 +1,621 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::env;
+use std::io::{self, IsTerminal};
 
-pub fn get_arguments(
-    positional_names: Vec<impl ToString>,
-) -> (
-    HashMap<String, String>,
-    HashMap<String, String>,
-    Vec<String>,
-) {
+use dialoguer::{Confirm, Input, MultiSelect, Select};
+
+use crate::meta::{GeneratorParameter, ParameterType};
+use crate::rustgen_error::{ErrorFormat, ErrorKind, RustgenError, RustgenResult};
+use crate::template::ConflictPolicy;
+
+/// Usage text of the whole application (`rustgen help`)
+pub const USAGE: &str = "\
+Usage: rustgen <command> [options]
+       rustgen <type> <action> <name> [options] [--variable=value...]
+
+Commands:
+    generate <type> <action> <name>    Renders the generator {type}/{action}
+    list                               Lists all available generators
+    help [command]                     Shows this help or the help of a command
+    help <type> <action>               Shows the templates and variables of a generator
+    init                               Creates a .rustgenrc.yml and the template directory
+    check                              Validates the config and all templates
+
+Options:
+    -h, --help                         Shows the help of the (sub-)command
+    --error-format=<text|json>         Prints errors as plain text (default) or json
+
+Exit codes:
+    0 success, 2 usage, 3 config, 4 template-parse, 5 render, 6 header-yaml,
+    7 missing-marker, 8 io, 9 conflict
+
+Run 'rustgen help <command>' for more information on a command.
+";
+
+/// Usage text of the `generate` command
+pub const USAGE_GENERATE: &str = "\
+Usage: rustgen generate <type> <action> <name> [options] [--variable=value...]
+
+Renders all templates found in {template_path}/{type}/{action}. Every --variable=value
+is forwarded into the templates. Arguments after '--' are never read as options.
+Declared parameters which are missing are asked for when running in a terminal.
+
+Options:
+    -f, --force                        Overwrite existing files (ignores every on_conflict)
+    -n, --dry-run                      Only show the diff of every file which would be written
+    --no-interactive                   Never ask for missing parameters
+    -h, --help                         Shows this help
+";
+
+/// Usage text of the `list` command
+pub const USAGE_LIST: &str = "\
+Usage: rustgen list
+
+Lists all generators found in the template directory with their templates, target paths
+and descriptions.
+";
+
+/// Usage text of the `help` command
+pub const USAGE_HELP: &str = "\
+Usage: rustgen help [command]
+       rustgen help <type> <action>
+
+Shows the general help, the help of the given command or the templates and variables
+of the generator {type}/{action}.
+";
+
+/// Usage text of the `init` command
+pub const USAGE_INIT: &str = "\
+Usage: rustgen init
+
+Creates a default .rustgenrc.yml and the template directory in the current directory.
+Already existing files are left untouched.
+";
+
+/// Usage text of the `check` command
+pub const USAGE_CHECK: &str = "\
+Usage: rustgen check
+
+Validates the .rustgenrc.yml and the syntax of every template.
+";
+
+/// The parsed command line
+#[derive(Debug, Clone)]
+pub struct Invocation {
+    pub command: Command,
+}
+
+/// The (sub-)command which should be executed
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// Renders a generator
+    Generate(GenerateArguments),
+
+    /// Lists all available generators
+    List,
+
+    /// Prints the usage of the application or one of its commands
+    Help(HelpTopic),
+
+    /// Creates a default config and template directory
+    Init,
+
+    /// Validates the config and templates
+    Check,
+}
+
+/// What the `help` command should describe
+#[derive(Debug, Clone, PartialEq)]
+pub enum HelpTopic {
+    General,
+    Command(String),
+    Generator { t_type: String, action: String },
+}
+
+/// Arguments for the `generate` command
+#[derive(Debug, Clone, Default)]
+pub struct GenerateArguments {
+    /// The generator type (first level folder in the template path)
+    pub t_type: String,
+
+    /// The generator action (second level folder in the template path)
+    pub action: String,
+
+    /// The name which is passed into the templates
+    pub name: String,
+
+    /// All `--variable=value` parameters, forwarded into the templates
+    pub variables: BTreeMap<String, String>,
+
+    /// `-f`, `--force`
+    pub force: bool,
+
+    /// `-n`, `--dry-run`
+    pub dry_run: bool,
+
+    /// `--no-interactive`
+    pub no_interactive: bool,
+}
+
+impl HelpTopic {
+    /// Returns the usage text for the topic. Generators have no static usage text
+    pub fn usage(&self) -> RustgenResult<&'static str> {
+        match self {
+            HelpTopic::Generator { t_type, action } => Err(RustgenError::new(
+                ErrorKind::Usage,
+                format!("The generator {} {} has no static usage", t_type, action),
+            )),
+            HelpTopic::General => Ok(USAGE),
+            HelpTopic::Command(command) => match command.as_str() {
+                "generate" => Ok(USAGE_GENERATE),
+                "list" => Ok(USAGE_LIST),
+                "help" => Ok(USAGE_HELP),
+                "init" => Ok(USAGE_INIT),
+                "check" => Ok(USAGE_CHECK),
+                _ => Err(RustgenError::new(
+                    ErrorKind::Usage,
+                    format!("Unknown command '{}'. See 'rustgen help'.", command),
+                )),
+            },
+        }
+    }
+}
+
+/// A single token of the command line after splitting up combined flags
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Positional(String),
+    Long(String, Option<String>),
+    Short(char),
+}
+
+/// Returns the arguments the application was started with
+pub fn get_arguments() -> Vec<String> {
     // Skipping the first as this is the name of the program
-    let args: Vec<String> = env::args().skip(1).collect();
-    let mut positional = Vec::new();
-    let mut named = HashMap::new();
+    env::args().skip(1).collect()
+}
+
+/// Returns the requested `--error-format`. Invalid values fall back to [ErrorFormat::Text], as
+/// this is used for printing errors, even when the arguments could not be parsed
+pub fn get_error_format(arguments: &[String]) -> ErrorFormat {
+    tokenize(arguments.to_vec())
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Long(name, Some(value)) if name == "error-format" => value.parse().ok(),
+            _ => None,
+        })
+        .next_back()
+        .unwrap_or(ErrorFormat::Text)
+}
+
+/// Parses the given arguments (without the program name) into an invocation
+pub fn parse_arguments(arguments: impl IntoIterator<Item = String>) -> RustgenResult<Invocation> {
+    let mut tokens = vec![];
+
+    // Global flags are valid for every command
+    for token in tokenize(arguments) {
+        match token {
+            Token::Long(name, value) if name == "error-format" => {
+                value
+                    .ok_or_else(|| {
+                        RustgenError::new(ErrorKind::Usage, "--error-format requires a value")
+                    })?
+                    .parse::<ErrorFormat>()?;
+            }
+            token => tokens.push(token),
+        }
+    }
+
+    let mut tokens = tokens.into_iter().peekable();
+
+    let command = match tokens.peek() {
+        Some(Token::Positional(command)) if is_command(command) => {
+            let command = command.clone();
+            tokens.next();
+
+            command
+        }
+        Some(token) if is_help_flag(token) => String::from("help"),
+        Some(_) => String::from("generate"),
+        None => String::from("help"),
+    };
+    let tokens: Vec<Token> = tokens.collect();
+
+    if command != "help" && tokens.iter().any(is_help_flag) {
+        return Ok(Invocation {
+            command: Command::Help(HelpTopic::Command(command)),
+        });
+    }
+
+    let command = match command.as_str() {
+        "generate" => Command::Generate(parse_generate(tokens)?),
+        "help" => Command::Help(parse_help(tokens)?),
+        "list" => {
+            expect_no_arguments("list", tokens)?;
+
+            Command::List
+        }
+        "init" => {
+            expect_no_arguments("init", tokens)?;
+
+            Command::Init
+        }
+        "check" => {
+            expect_no_arguments("check", tokens)?;
+
+            Command::Check
+        }
+        _ => unreachable!("Commands are checked by is_command"),
+    };
+
+    Ok(Invocation { command })
+}
 
-    for arg in &args {
-        if arg.starts_with("--") {
-            let name: String = arg.chars().skip(2).take_while(|c| c.ne(&'=')).collect();
-            let value: String = arg.chars().skip_while(|c| c.ne(&'=')).skip(1).collect();
+fn is_command(name: &str) -> bool {
+    matches!(name, "generate" | "list" | "help" | "init" | "check")
+}
+
+fn is_help_flag(token: &Token) -> bool {
+    matches!(token, Token::Short('h')) || matches!(token, Token::Long(name, None) if name == "help")
+}
+
+/// Splits the raw arguments into tokens
+///
+/// - `--name=value` and `--name` become [Token::Long]
+/// - `-abc` becomes the three [Token::Short]s `a`, `b` and `c`
+/// - everything after `--` as well as a single `-` is positional
+fn tokenize(arguments: impl IntoIterator<Item = String>) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut only_positional = false;
+
+    for argument in arguments {
+        if only_positional || argument == "-" || !argument.starts_with('-') {
+            tokens.push(Token::Positional(argument));
+        } else if argument == "--" {
+            only_positional = true;
+        } else if let Some(long) = argument.strip_prefix("--") {
+            match long.split_once('=') {
+                Some((name, value)) => {
+                    tokens.push(Token::Long(name.to_string(), Some(value.to_string())))
+                }
+                None => tokens.push(Token::Long(long.to_string(), None)),
+            }
+        } else {
+            tokens.extend(argument.chars().skip(1).map(Token::Short));
+        }
+    }
+
+    tokens
+}
+
+fn parse_generate(tokens: Vec<Token>) -> RustgenResult<GenerateArguments> {
+    let mut arguments = GenerateArguments::default();
+    let mut positional = vec![];
+
+    for token in tokens {
+        match token {
+            Token::Positional(value) => positional.push(value),
+            Token::Short('f') => arguments.force = true,
+            Token::Short('n') => arguments.dry_run = true,
+            Token::Short(flag) => return Err(unknown_flag(format!("-{}", flag))),
+            Token::Long(name, value) => match (name.as_str(), value) {
+                ("force", None) => arguments.force = true,
+                ("dry-run", None) => arguments.dry_run = true,
+                ("no-interactive", None) => arguments.no_interactive = true,
+                ("force", Some(_)) | ("dry-run", Some(_)) | ("no-interactive", Some(_)) => {
+                    return Err(RustgenError::new(
+                        ErrorKind::Usage,
+                        format!("The flag --{} does not take a value", name),
+                    ))
+                }
+                ("", _) => return Err(unknown_flag(format!("--{}", name))),
+                (_, Some(value)) => {
+                    arguments.variables.insert(name, value);
+                }
+                (_, None) => {
+                    return Err(RustgenError::new(
+                        ErrorKind::Usage,
+                        format!(
+                        "Unknown flag '--{0}'. Template variables have to be passed as --{0}=value",
+                        name
+                    ),
+                    ))
+                }
+            },
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    arguments.t_type = positional.next().ok_or_else(|| missing_argument("type"))?;
+    arguments.action = positional
+        .next()
+        .ok_or_else(|| missing_argument("action"))?;
+    arguments.name = positional.next().ok_or_else(|| missing_argument("name"))?;
+
+    if let Some(unexpected) = positional.next() {
+        return Err(RustgenError::new(
+            ErrorKind::Usage,
+            format!(
+                "Unexpected argument '{}'. See 'rustgen help generate'.",
+                unexpected
+            ),
+        ));
+    }
+
+    Ok(arguments)
+}
+
+fn parse_help(tokens: Vec<Token>) -> RustgenResult<HelpTopic> {
+    let mut positional = vec![];
+
+    for token in tokens {
+        match token {
+            Token::Positional(value) => positional.push(value),
+            token if is_help_flag(&token) => {}
+            Token::Short(flag) => return Err(unknown_flag(format!("-{}", flag))),
+            Token::Long(name, _) => return Err(unknown_flag(format!("--{}", name))),
+        }
+    }
+
+    match positional.as_slice() {
+        [] => Ok(HelpTopic::General),
+        [command] => Ok(HelpTopic::Command(command.clone())),
+        [t_type, action] => Ok(HelpTopic::Generator {
+            t_type: t_type.clone(),
+            action: action.clone(),
+        }),
+        _ => Err(RustgenError::new(
+            ErrorKind::Usage,
+            "Too many arguments. See 'rustgen help help'.",
+        )),
+    }
+}
+
+fn expect_no_arguments(command: &str, tokens: Vec<Token>) -> RustgenResult<()> {
+    match tokens.into_iter().next() {
+        None => Ok(()),
+        Some(Token::Positional(value)) => Err(RustgenError::new(
+            ErrorKind::Usage,
+            format!(
+                "Unexpected argument '{}'. See 'rustgen help {}'.",
+                value, command
+            ),
+        )),
+        Some(Token::Short(flag)) => Err(unknown_flag(format!("-{}", flag))),
+        Some(Token::Long(name, _)) => Err(unknown_flag(format!("--{}", name))),
+    }
+}
+
+fn unknown_flag(flag: impl ToString) -> RustgenError {
+    RustgenError::new(
+        ErrorKind::Usage,
+        format!("Unknown flag '{}'. See 'rustgen help'.", flag.to_string()),
+    )
+}
+
+fn missing_argument(name: &str) -> RustgenError {
+    RustgenError::new(
+        ErrorKind::Usage,
+        format!("Missing argument '{}'. See 'rustgen help generate'.", name),
+    )
+}
 
-            named.insert(name, value);
+/// Returns whether the user can be asked for input (stdin and stderr are terminals and rustgen
+/// does not run in a CI, detected by the `CI` environment variable)
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal() && io::stderr().is_terminal() && env::var_os("CI").is_none()
+}
+
+/// Asks the user for the value of a parameter. Depending on the type and allowed values this is
+/// a text input, a confirmation, a single or a multi select
+pub fn prompt_parameter(parameter: &GeneratorParameter) -> RustgenResult<String> {
+    let prompt = match &parameter.description {
+        Some(description) => format!("{} ({})", parameter.name, description),
+        None => parameter.name.clone(),
+    };
+    let default = parameter.default.clone().unwrap_or_default();
+
+    let value = match (parameter.parameter_type, parameter.values.is_empty()) {
+        (ParameterType::Bool, _) => {
+            let confirmed = Confirm::new()
+                .with_prompt(prompt)
+                .default(!parameter.validate(&default)?.is_empty())
+                .interact()?;
+
+            confirmed.to_string()
+        }
+        (ParameterType::List, false) => {
+            let defaults: Vec<&str> = default.split(',').map(str::trim).collect();
+            let selected: Vec<bool> = parameter
+                .values
+                .iter()
+                .map(|value| defaults.contains(&value.as_str()))
+                .collect();
+            let chosen = MultiSelect::new()
+                .with_prompt(prompt)
+                .items(&parameter.values)
+                .defaults(&selected)
+                .interact()?;
+
+            chosen
+                .into_iter()
+                .map(|index| parameter.values[index].clone())
+                .collect::<Vec<String>>()
+                .join(",")
+        }
+        (_, false) => {
+            let chosen = Select::new()
+                .with_prompt(prompt)
+                .items(&parameter.values)
+                .default(
+                    parameter
+                        .values
+                        .iter()
+                        .position(|value| value == &default)
+                        .unwrap_or(0),
+                )
+                .interact()?;
+
+            parameter.values[chosen].clone()
+        }
+        (_, true) => {
+            let mut input = Input::<String>::new()
+                .with_prompt(prompt)
+                .allow_empty(!parameter.required)
+                .validate_with(|input: &String| -> Result<(), String> {
+                    if input.is_empty() && !parameter.required {
+                        return Ok(());
+                    }
+
+                    parameter
+                        .validate(input)
+                        .map(|_| ())
+                        .map_err(|error| error.to_string())
+                });
+
+            if !default.is_empty() {
+                input = input.default(default);
+            }
+
+            input.interact_text()?
+        }
+    };
+
+    Ok(value)
+}
+
+/// Asks the user what to do with an already existing file
+pub fn prompt_conflict(path: &str) -> RustgenResult<ConflictPolicy> {
+    let choices = [
+        ("Overwrite", ConflictPolicy::Overwrite),
+        ("Skip", ConflictPolicy::Skip),
+        ("Write as .new", ConflictPolicy::New),
+        ("Abort", ConflictPolicy::Fail),
+    ];
+    let labels: Vec<&str> = choices.iter().map(|(label, _)| *label).collect();
+    let chosen = Select::new()
+        .with_prompt(format!("The file {} already exists", path))
+        .items(&labels)
+        .default(0)
+        .interact()?;
+
+    Ok(choices[chosen].1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arguments(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    fn generate(line: &str) -> RustgenResult<GenerateArguments> {
+        match parse_arguments(arguments(line))?.command {
+            Command::Generate(arguments) => Ok(arguments),
+            command => panic!("Expected generate for {:?}, got {:?}", line, command),
         }
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let cases = [
+            ("-fn", vec![Token::Short('f'), Token::Short('n')]),
+            (
+                "--x=1 --flag",
+                vec![
+                    Token::Long(String::from("x"), Some(String::from("1"))),
+                    Token::Long(String::from("flag"), None),
+                ],
+            ),
+            ("-", vec![Token::Positional(String::from("-"))]),
+            (
+                "a -- -b --c",
+                vec![
+                    Token::Positional(String::from("a")),
+                    Token::Positional(String::from("-b")),
+                    Token::Positional(String::from("--c")),
+                ],
+            ),
+        ];
 
-        if arg.starts_with("-") {
-            continue; // I dont care (yet) about short arguments
+        for (line, expected) in cases {
+            assert_eq!(tokenize(arguments(line)), expected, "{}", line);
         }
+    }
+
+    #[test]
+    fn test_parse_generate() {
+        let parsed = generate("generate entity new my-entity -fn --sub=a").unwrap();
 
-        positional.push(arg.clone());
+        assert_eq!(
+            (parsed.t_type.as_str(), parsed.action.as_str()),
+            ("entity", "new")
+        );
+        assert_eq!(parsed.name, "my-entity");
+        assert!(parsed.force && parsed.dry_run && !parsed.no_interactive);
+        assert_eq!(parsed.variables.get("sub").map(String::as_str), Some("a"));
+
+        // The short form and names starting with a dash after `--`
+        assert_eq!(
+            generate("entity new -- -my-entity").unwrap().name,
+            "-my-entity"
+        );
+        assert_eq!(generate("entity new -").unwrap().name, "-");
     }
 
-    let mut positional_mapped = HashMap::new();
+    #[test]
+    fn test_parse_generate_errors() {
+        let cases = [
+            "entity new name --x",
+            "entity new name --force=yes",
+            "entity new name -x",
+            "entity new name --=1",
+            "entity new",
+            "entity new name other",
+            "entity new name --error-format",
+            "entity new name --error-format=xml",
+        ];
+
+        for line in cases {
+            let error = match parse_arguments(arguments(line)) {
+                Ok(invocation) => panic!("{:?} was parsed as {:?}", line, invocation),
+                Err(error) => error,
+            };
 
-    for (index, value) in positional.iter().enumerate() {
-        if let Some(name) = positional_names.get(index) {
-            positional_mapped.insert(name.to_string(), value.clone());
+            assert_eq!(error.kind(), ErrorKind::Usage, "{}", line);
         }
     }
 
-    (named, positional_mapped, positional)
+    #[test]
+    fn test_parse_help() {
+        let cases = [
+            ("", HelpTopic::General),
+            ("-h", HelpTopic::General),
+            ("help", HelpTopic::General),
+            ("help list", HelpTopic::Command(String::from("list"))),
+            ("list --help", HelpTopic::Command(String::from("list"))),
+            (
+                "entity new name -h",
+                HelpTopic::Command(String::from("generate")),
+            ),
+            (
+                "help entity new",
+                HelpTopic::Generator {
+                    t_type: String::from("entity"),
+                    action: String::from("new"),
+                },
+            ),
+        ];
+
+        for (line, expected) in cases {
+            match parse_arguments(arguments(line)).unwrap().command {
+                Command::Help(topic) => assert_eq!(topic, expected, "{}", line),
+                command => panic!("Expected help for {:?}, got {:?}", line, command),
+            }
+        }
+
+        assert!(parse_arguments(arguments("help a b c")).is_err());
+        assert!(parse_arguments(arguments("list extra")).is_err());
+    }
 }
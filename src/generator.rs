@@ -0,0 +1,336 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::str::Chars;
+use std::{fs, io};
+
+use crate::meta::{GeneratorMeta, Scaffold, STATIC_DIRECTORY};
+use crate::rustgen_error::{ErrorKind, RustgenError, RustgenResult};
+use crate::template::{PreProcessor, TemplateHeader};
+
+/// A generator is a directory `{template_path}/{type}/{action}` containing templates
+#[derive(Debug, Clone)]
+pub struct Generator {
+    pub t_type: String,
+    pub action: String,
+    pub path: PathBuf,
+}
+
+/// Information about a single template of a generator, used by `rustgen list` and `rustgen help`
+#[derive(Debug)]
+pub struct TemplateInfo {
+    pub file_name: String,
+
+    /// The header, rendered with the preview data if possible, raw otherwise
+    pub header: RustgenResult<TemplateHeader>,
+
+    /// All variables the template reads
+    pub variables: RustgenResult<BTreeSet<String>>,
+}
+
+impl Generator {
+    pub fn new(template_path: &Path, t_type: impl ToString, action: impl ToString) -> Self {
+        let t_type = t_type.to_string();
+        let action = action.to_string();
+        let path = template_path.join(&t_type).join(&action);
+
+        Self {
+            t_type,
+            action,
+            path,
+        }
+    }
+
+    /// Finds all generators in the template path, sorted by type and action
+    pub fn discover(template_path: &Path) -> RustgenResult<Vec<Generator>> {
+        let mut generators = vec![];
+
+        for t_type in sub_directories(template_path)? {
+            for action in sub_directories(&template_path.join(&t_type))? {
+                generators.push(Generator::new(template_path, &t_type, action));
+            }
+        }
+
+        Ok(generators)
+    }
+
+    /// Reads the optional metadata file (see [GeneratorMeta])
+    pub fn meta(&self) -> RustgenResult<GeneratorMeta> {
+        GeneratorMeta::read(&self.path)
+    }
+
+    /// Returns the paths of all templates of this generator. Files starting with an underscore
+    /// (like the `_meta.yml`) are reserved and no templates, static files (see
+    /// [crate::meta::StaticFiles]) are copied instead. Scaffolding generators (see
+    /// [crate::meta::Scaffold]) include the templates of all sub directories
+    ///
+    /// The templates are returned in the order they run: the ones of the `order` of the metadata
+    /// first, then all others sorted by name
+    pub fn templates(&self) -> RustgenResult<Vec<PathBuf>> {
+        // An invalid metadata file is reported by the commands reading it
+        let meta = self.meta().unwrap_or_default();
+        let mut templates: Vec<PathBuf> = self
+            .files(&meta)?
+            .into_iter()
+            .filter(|path| !meta.static_files.is_static(path))
+            .collect();
+        let mut ordered = vec![];
+
+        for name in &meta.order {
+            let position = templates
+                .iter()
+                .position(|path| path == &self.path.join(name))
+                .ok_or_else(|| {
+                    RustgenError::new(
+                        ErrorKind::Config,
+                        format!(
+                            "The order of the generator {} {} contains {}, which is no template \
+                            (or listed twice)",
+                            self.t_type, self.action, name
+                        ),
+                    )
+                })?;
+
+            ordered.push(templates.remove(position));
+        }
+
+        ordered.append(&mut templates);
+
+        Ok(ordered)
+    }
+
+    /// Returns the static files of this generator with the (not yet rendered) path they are
+    /// copied to: all files of the `_static/` folder and the files with a static extension
+    pub fn static_files(&self, meta: &GeneratorMeta) -> RustgenResult<Vec<(PathBuf, String)>> {
+        let mut files: Vec<(PathBuf, String)> = self
+            .files(meta)?
+            .into_iter()
+            .filter(|path| meta.static_files.is_static(path))
+            .filter_map(|path| {
+                let destination = match meta.scaffold {
+                    Some(_) => self.default_path(meta, &path),
+                    None => meta.static_files.destination(Path::new(path.file_name()?)),
+                };
+
+                Some((path, destination))
+            })
+            .collect();
+        let static_directory = self.path.join(STATIC_DIRECTORY);
+
+        // Static files are copied as they are, so reserved names are allowed (e.g. `_redirects`)
+        for path in files_recursive(&static_directory, false)? {
+            let relative = path.strip_prefix(&static_directory).unwrap_or(&path);
+            let destination = meta.static_destination(relative);
+
+            files.push((path, destination));
+        }
+
+        Ok(files)
+    }
+
+    /// The (not yet rendered) path of a template without a path in its header: the file name
+    /// without `.hbs`, for scaffolding generators the path the file is mirrored to
+    pub fn default_path(&self, meta: &GeneratorMeta, path: &Path) -> String {
+        let relative = path.strip_prefix(&self.path).unwrap_or(path);
+
+        match &meta.scaffold {
+            Some(scaffold) => scaffold.destination(relative),
+            None => Scaffold::default().destination(Path::new(
+                relative.file_name().unwrap_or(relative.as_os_str()),
+            )),
+        }
+    }
+
+    /// All files of the generator folder (and its sub directories for scaffolding generators),
+    /// except the reserved ones starting with an underscore
+    fn files(&self, meta: &GeneratorMeta) -> RustgenResult<Vec<PathBuf>> {
+        let entries = fs::read_dir(&self.path).map_err(|error| {
+            RustgenError::new(
+                ErrorKind::Usage,
+                format!("Templates not found in {}", self.path.display()),
+            )
+            .with_source(error)
+        })?;
+        let mut files = vec![];
+
+        for path in entries
+            .flatten()
+            .filter(|entry| !entry.file_name().to_string_lossy().starts_with('_'))
+            .map(|entry| entry.path())
+        {
+            if path.is_file() {
+                files.push(path);
+            } else if path.is_dir() && meta.scaffold.is_some() {
+                files.append(&mut files_recursive(&path, true)?);
+            }
+        }
+
+        files.sort_by(|a, b| compare_names(&a.to_string_lossy(), &b.to_string_lossy()));
+
+        Ok(files)
+    }
+
+    /// Reads all templates and collects their information. The data is used for rendering the
+    /// headers (see [PreProcessor::preview_header])
+    pub fn inspect(&self, data: &BTreeMap<String, String>) -> RustgenResult<Vec<TemplateInfo>> {
+        let meta = self.meta().unwrap_or_default();
+        let mut infos = vec![];
+
+        for path in self.templates()? {
+            let processor = PreProcessor::new(read_template(&path)?)?
+                .with_path(Some(self.default_path(&meta, &path)));
+
+            infos.push(TemplateInfo {
+                file_name: path
+                    .strip_prefix(&self.path)
+                    .unwrap_or(&path)
+                    .to_string_lossy()
+                    .to_string(),
+                header: processor.preview_header(data),
+                variables: processor.variables(),
+            });
+        }
+
+        Ok(infos)
+    }
+}
+
+/// Reads a template, binary files fail with a hint to the static files
+pub fn read_template(path: &Path) -> RustgenResult<String> {
+    fs::read_to_string(path).map_err(|error| match error.kind() {
+        io::ErrorKind::InvalidData => RustgenError::new(
+            ErrorKind::TemplateParse,
+            format!(
+                "The template {} is no text file. Add its extension to `static.extensions` in \
+                the _meta.yml or move it into the {} folder to copy it as is",
+                path.display(),
+                STATIC_DIRECTORY
+            ),
+        )
+        .with_source(error),
+        _ => error.into(),
+    })
+}
+
+/// All files in the directory and its sub directories, sorted. A missing directory has no files.
+/// Files and directories starting with an underscore are skipped if `skip_reserved` is set
+fn files_recursive(directory: &Path, skip_reserved: bool) -> RustgenResult<Vec<PathBuf>> {
+    let mut files = vec![];
+
+    if !directory.is_dir() {
+        return Ok(files);
+    }
+
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if skip_reserved && entry.file_name().to_string_lossy().starts_with('_') {
+            continue;
+        }
+
+        match path.is_dir() {
+            true => files.append(&mut files_recursive(&path, skip_reserved)?),
+            false => files.push(path),
+        }
+    }
+
+    files.sort_by(|a, b| compare_names(&a.to_string_lossy(), &b.to_string_lossy()));
+
+    Ok(files)
+}
+
+/// Compares file names like humans do, numbers are compared by their value (`2_a` < `10_a`), so
+/// numeric prefixes define the order
+fn compare_names(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        let ordering = match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                let (x_value, y_value) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+
+                x_value
+                    .len()
+                    .cmp(&y_value.len())
+                    .then_with(|| x_value.cmp(y_value))
+                    .then_with(|| x.len().cmp(&y.len()))
+            }
+            (Some(x), Some(y)) => {
+                a.next();
+                b.next();
+
+                x.cmp(&y)
+            }
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+fn take_number(chars: &mut Peekable<Chars>) -> String {
+    let mut number = String::new();
+
+    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+        number.push(digit);
+    }
+
+    number
+}
+
+fn sub_directories(path: &Path) -> RustgenResult<Vec<String>> {
+    let entries = fs::read_dir(path).map_err(|error| {
+        RustgenError::new(
+            ErrorKind::Config,
+            format!("Template directory {} not found", path.display()),
+        )
+        .with_source(error)
+    })?;
+    let mut directories: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+
+    directories.sort();
+
+    Ok(directories)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_names() {
+        let mut names = vec![
+            "10_b.hbs",
+            "register.hbs",
+            "2_a.hbs",
+            "02_c.hbs",
+            "controller.hbs",
+        ];
+
+        names.sort_by(|a, b| compare_names(a, b));
+
+        assert_eq!(
+            names,
+            vec![
+                "2_a.hbs",
+                "02_c.hbs",
+                "10_b.hbs",
+                "controller.hbs",
+                "register.hbs"
+            ]
+        );
+    }
+}
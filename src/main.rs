@@ -16,22 +16,31 @@
 //!
 //! ### Usage
 //!
-//! Rustgen gets invoked with at least 3 positional arguments and depending on the template some
-//! named parameters as well. The parameters will entirely be forwarded into the template so you can
-//! use them to modify, for example, if your generated code should end up in some subdirectory.
+//! Rustgen gets invoked with a command (`generate`, `list`, `help`, `init` or `check`). The
+//! `generate` command takes 3 positional arguments and depending on the template some named
+//! parameters as well. The parameters will entirely be forwarded into the template so you can use
+//! them to modify, for example, if your generated code should end up in some subdirectory.
 //!
 //! The command will look like this:
 //!
 //! ```bash
 //! # The raw command
+//! rustgen generate {type} {action} {name}
+//!
+//! # The short form (without the command) still works
 //! rustgen {type} {action} {name}
 //!
 //! # An example
-//! rustgen entity generate my-entity --subdirectory=entities
+//! rustgen generate entity generate my-entity --subdirectory=entities
+//!
+//! # A name starting with a dash has to be passed after the `--` terminator
+//! rustgen generate entity generate -- -my-entity
 //! ```
 //!
 //! The arguments `type`, `action` and `name` will be available in the template as well as the (in
-//! this case) parameter `subdirectory`.
+//! this case) parameter `subdirectory`. Template parameters always need the `--name=value` form,
+//! unknown flags are reported as errors. Run `rustgen help` for an overview of all commands and
+//! flags.
 //!
 //! ### Getting started
 //!
@@ -57,6 +66,11 @@
 //! `action` and `name`, as well as all of your given parameters and default values from your config
 //! (explained later on).
 //!
+//! A template starts with a yaml header between two lines containing only `---`, which tells
+//! rustgen where and how to write the rendered template (see [here](crate::template::TemplateHeader)).
+//! The header is optional: templates without one (or without a `path`) create a file named like
+//! the template without `.hbs` (e.g. `{{name}}.md.hbs`).
+//!
 //! For helpers there are (besides of the, in the [library included](https://docs.rs/handlebars/3.5.4/handlebars/#built-in-helpers)
 //! ones) the following which are documented in their links:
 //!
@@ -65,6 +79,7 @@
 //! - [SetHelper](crate::template::SetHelper)
 //! - [ConcatHelper](crate::template::ConcatHelper)
 //! - [TimeHelper](crate::template::TimeHelper)
+//! - [CaptureHelper](crate::template::CaptureHelper)
 //!
 //! And helpers for changing the case of a text, for the following formats:
 //!
@@ -86,6 +101,32 @@
 //! They don't have a specific documentation as they all work in the same way: `{{upper_case variable_name}}`.
 //! For an overview of what they produce you can take a look at the [crate's documentation](https://docs.rs/convert_case/0.4.0/convert_case/enum.Case.html#variants).
 //!
+//! ### Exit codes and errors
+//!
+//! Rustgen exits with `0` on success and with a code depending on the [kind](crate::rustgen_error::ErrorKind)
+//! of error otherwise, so scripts can tell why a generation failed. Passing `--error-format=json`
+//! prints errors as a single json object (`kind`, `code`, `message` and `causes`) to stderr.
+//!
+//! ### Generator metadata
+//!
+//! Each generator folder can contain an optional `_meta.yml` which describes the generator and
+//! declares its parameters (type, description, default, allowed values, ...). Declared parameters
+//! are validated before any template gets rendered and are shown by `rustgen help {type} {action}`.
+//! When running in a terminal, rustgen asks for declared parameters which are missing on the
+//! command line (this can be disabled with `--no-interactive`).
+//! The available options are described [here](crate::meta::GeneratorMeta). Files and directories
+//! starting with an underscore (also in sub directories of scaffolding generators) are never
+//! rendered as templates.
+//!
+//! Static files like images or fonts are copied byte-for-byte instead of being rendered: every file
+//! in the `_static/` folder of a generator and files with a binary extension (configurable in the
+//! `_meta.yml`, see [here](crate::meta::StaticFiles)). Their file names can contain handlebars
+//! expressions as well.
+//!
+//! A generator with a `scaffold` section in its `_meta.yml` mirrors its whole directory tree into
+//! a destination (for scaffolding whole projects), with handlebars expressions in directory and file
+//! names and optional headers. See [here](crate::meta::Scaffold) for the details.
+//!
 //! ## Configure rustgen
 //!
 //! For configuring rustgen you have to create a .rustgenrc.yml or .yaml file in your project folder.
@@ -100,16 +141,26 @@ extern crate handlebars;
 extern crate serde;
 extern crate serde_yaml;
 
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::path::PathBuf;
-use std::{env, fs};
+use std::{env, process};
 
-use crate::console::get_arguments;
-use crate::rustgen_error::{RustgenError, RustgenResult};
-use crate::template::{PreProcessor, Writer};
+use handlebars::Template;
+
+use crate::config::ApplicationConfig;
+use crate::console::{
+    get_arguments, get_error_format, parse_arguments, Command, GenerateArguments, HelpTopic,
+};
+use crate::generator::{read_template, Generator, TemplateInfo};
+use crate::meta::{GeneratorMeta, GeneratorParameter};
+use crate::rustgen_error::{ErrorKind, RustgenError, RustgenResult};
+use crate::template::assets::render_optional_path;
+use crate::template::{Asset, PreProcessor, Workspace, WriteOptions, Writer};
 
 pub mod config;
 pub mod console;
+pub mod generator;
+pub mod meta;
 pub mod rustgen_error;
 pub mod template;
 
@@ -119,68 +170,333 @@ fn insert_default_data(defaults: &HashMap<String, String>, data: &mut BTreeMap<S
     }
 }
 
-fn generate(named: HashMap<String, String>, mapped: HashMap<String, String>) -> RustgenResult<()> {
-    let t_type = String::from(
-        mapped
-            .get("type")
-            .ok_or(RustgenError::new("Missing parameter 'type'"))?,
-    );
-    let action = String::from(
-        mapped
-            .get("action")
-            .ok_or(RustgenError::new("Missing parameter 'action'"))?,
-    );
-    let name = String::from(
-        mapped
-            .get("name")
-            .ok_or(RustgenError::new("Missing parameter 'name'"))?,
-    );
+fn generate(arguments: GenerateArguments) -> RustgenResult<()> {
     let mut data = BTreeMap::<String, String>::new();
-    let config = config::read();
+    let config = config::read()?;
     insert_default_data(&config.default, &mut data);
 
-    for (key, value) in &named {
+    for (key, value) in &arguments.variables {
         data.insert(key.clone(), value.clone());
     }
 
     let cwd = env::current_dir()?;
-    let templates = cwd.join(format!("{}/{}/{}", &config.template_path, &t_type, &action));
-
-    data.insert(String::from("type"), t_type);
-    data.insert(String::from("action"), action);
-    data.insert(String::from("name"), name);
-
-    for directory in fs::read_dir(templates.clone()).or(Err(RustgenError::new(format!(
-        "Templates not found in {}",
-        templates.display()
-    ))))? {
-        if let Ok(entry) = directory {
-            if entry.path().is_file() {
-                generate_file(entry.path(), data.clone())?;
+    let generator = Generator::new(
+        &cwd.join(&config.template_path),
+        &arguments.t_type,
+        &arguments.action,
+    );
+
+    data.insert(String::from("type"), arguments.t_type);
+    data.insert(String::from("action"), arguments.action);
+    data.insert(String::from("name"), arguments.name);
+
+    let templates = generator.templates()?;
+    let interactive = !arguments.no_interactive && console::is_interactive();
+    let meta = generator.meta()?;
+    meta.apply(&mut data, interactive)?;
+
+    let mut workspace = Workspace::new(cwd);
+    let options = WriteOptions {
+        on_conflict: config.on_conflict,
+        force: arguments.force,
+        interactive,
+    };
+
+    for template in templates {
+        // Used by templates without a path in the header
+        let path = match render_optional_path(&generator.default_path(&meta, &template), &data)? {
+            Some(path) => path,
+            None => continue,
+        };
+
+        generate_file(template, path, data.clone(), &mut workspace, &options)?;
+    }
+
+    for (source, path) in generator.static_files(&meta)? {
+        if let Some(path) = render_optional_path(&path, &data)? {
+            Asset::new(source, path, options.clone()).copy(&mut workspace)?;
+        }
+    }
+
+    // Nothing is written before every template succeeded
+    match arguments.dry_run {
+        true => workspace.print_diff(),
+        false => workspace.commit()?,
+    }
+
+    Ok(())
+}
+
+/// Renders and writes a template, the default path is used if the header sets none
+fn generate_file(
+    path: PathBuf,
+    default_path: String,
+    data: BTreeMap<String, String>,
+    workspace: &mut Workspace,
+    options: &WriteOptions,
+) -> RustgenResult<()> {
+    let template = read_template(&path)?;
+    let processor = PreProcessor::new(template)?.with_path(Some(default_path));
+    let (header, template) = processor.extract_config_template(data).map_err(|error| {
+        RustgenError::new(error.kind(), format!("{}: {}", path.display(), error)).with_source(error)
+    })?;
+
+    Writer::new(header, template, options.clone()).run_action(workspace)?;
+
+    Ok(())
+}
+
+/// The data available before knowing the name, used for previewing the template headers
+fn preview_data(config: &ApplicationConfig, generator: &Generator) -> BTreeMap<String, String> {
+    let mut data = BTreeMap::new();
+    insert_default_data(&config.default, &mut data);
+
+    data.insert(String::from("type"), generator.t_type.clone());
+    data.insert(String::from("action"), generator.action.clone());
+
+    data
+}
+
+fn print_template(template: &TemplateInfo) {
+    match &template.header {
+        Ok(header) => {
+            println!(
+                "    {} ({} {})",
+                template.file_name,
+                header.action.describe(),
+                header.path
+            );
+
+            if let Some(description) = &header.description {
+                println!("        {}", description);
+            }
+        }
+        Err(error) => println!("    {} (invalid header: {})", template.file_name, error),
+    }
+}
+
+fn list() -> RustgenResult<()> {
+    let config = config::read()?;
+    let template_path = env::current_dir()?.join(&config.template_path);
+
+    for generator in Generator::discover(&template_path)? {
+        println!("{} {}", generator.t_type, generator.action);
+
+        match generator.meta() {
+            Ok(GeneratorMeta {
+                description: Some(description),
+                ..
+            }) => println!("  {}", description),
+            Ok(_) => {}
+            Err(error) => println!("  (invalid metadata: {})", error),
+        }
+
+        for template in generator.inspect(&preview_data(&config, &generator))? {
+            print_template(&template);
+        }
+
+        println!();
+    }
+
+    Ok(())
+}
+
+fn print_parameter(parameter: &GeneratorParameter, config_default: Option<&String>) {
+    let mut details = vec![parameter.parameter_type.name().to_string()];
+
+    if parameter.required {
+        details.push(String::from("required"));
+    }
+
+    match (config_default, &parameter.default) {
+        (Some(default), _) => details.push(format!("default from config: {:?}", default)),
+        (None, Some(default)) => details.push(format!("default: {:?}", default)),
+        (None, None) => {}
+    }
+
+    if !parameter.values.is_empty() {
+        details.push(format!("values: {}", parameter.values.join(", ")));
+    }
+
+    if let Some(pattern) = &parameter.pattern {
+        details.push(format!("pattern: {}", pattern));
+    }
+
+    println!("    {} ({})", parameter.name, details.join(", "));
+
+    if let Some(description) = &parameter.description {
+        println!("        {}", description);
+    }
+}
+
+/// Prints the templates of a generator and the variables they read
+fn help_generator(t_type: String, action: String) -> RustgenResult<()> {
+    let config = config::read()?;
+    let template_path = env::current_dir()?.join(&config.template_path);
+    let generator = Generator::new(&template_path, t_type, action);
+    let templates = generator.inspect(&preview_data(&config, &generator))?;
+    let meta = generator.meta()?;
+    let mut variables = BTreeSet::new();
+
+    for template in &templates {
+        if let Ok(template_variables) = &template.variables {
+            variables.extend(template_variables.iter().cloned());
+        }
+    }
+
+    for builtin in &["type", "action", "name"] {
+        variables.remove(*builtin);
+    }
+
+    for parameter in &meta.parameters {
+        variables.remove(&parameter.name);
+    }
+
+    let usage: String = meta
+        .parameters
+        .iter()
+        .map(
+            |parameter| match parameter.required && parameter.default.is_none() {
+                true => format!(
+                    " --{}=<{}>",
+                    parameter.name,
+                    parameter.parameter_type.name()
+                ),
+                false => format!(
+                    " [--{}=<{}>]",
+                    parameter.name,
+                    parameter.parameter_type.name()
+                ),
+            },
+        )
+        .chain(
+            variables
+                .iter()
+                .map(|variable| format!(" [--{}=...]", variable)),
+        )
+        .collect();
+
+    println!(
+        "Usage: rustgen generate {} {} <name>{}",
+        generator.t_type, generator.action, usage
+    );
+
+    if let Some(description) = &meta.description {
+        println!();
+        println!("{}", description);
+    }
+
+    println!();
+    println!("Templates:");
+
+    for template in &templates {
+        print_template(template);
+    }
+
+    println!();
+    println!("Variables:");
+    println!("    type, action, name (always set from the command line)");
+
+    for parameter in &meta.parameters {
+        print_parameter(parameter, config.default.get(&parameter.name));
+    }
+
+    for variable in &variables {
+        match config.default.get(variable) {
+            Some(default) => println!("    {} (default from config: {:?})", variable, default),
+            None => println!("    {}", variable),
+        }
+    }
+
+    Ok(())
+}
+
+/// Validates the config, the generator metadata and the handlebars syntax of every template
+/// (header and body)
+fn check() -> RustgenResult<()> {
+    let config = config::read()?;
+    let template_path = env::current_dir()?.join(&config.template_path);
+    let mut invalid = 0;
+
+    for generator in Generator::discover(&template_path)? {
+        let meta = generator.meta().unwrap_or_else(|error| {
+            invalid += 1;
+            eprintln!("{}", error);
+
+            GeneratorMeta::default()
+        });
+
+        for path in generator.templates()? {
+            let template = match read_template(&path) {
+                Ok(template) => template,
+                Err(error) => {
+                    invalid += 1;
+                    eprintln!("{}", error);
+                    continue;
+                }
+            };
+            let (header, body) = match PreProcessor::new(template)?.extract_parts() {
+                Ok(parts) => parts,
+                Err(error) => {
+                    invalid += 1;
+                    eprintln!("{}: {}", path.display(), error);
+                    continue;
+                }
+            };
+            let default_path = generator.default_path(&meta, &path);
+
+            if let Err(error) = Template::compile(&header)
+                .and(Template::compile(&body))
+                .and(Template::compile(&default_path))
+            {
+                invalid += 1;
+                eprintln!("{}: {}", path.display(), error);
             }
         }
+
+        for (source, path) in generator.static_files(&meta)? {
+            if let Err(error) = Template::compile(&path) {
+                invalid += 1;
+                eprintln!("{}: {}", source.display(), error);
+            }
+        }
+    }
+
+    if invalid > 0 {
+        return Err(RustgenError::new(
+            ErrorKind::TemplateParse,
+            format!("{} invalid template(s) or metadata file(s) found", invalid),
+        ));
     }
 
+    println!("All templates are valid");
+
     Ok(())
 }
 
-fn generate_file(path: PathBuf, data: BTreeMap<String, String>) -> RustgenResult<()> {
-    let template = fs::read_to_string(path)?;
-    let processor = PreProcessor::new(template).unwrap();
-    let (header, template) = processor.extract_config_template(data)?;
+fn help(topic: HelpTopic) -> RustgenResult<()> {
+    if let HelpTopic::Generator { t_type, action } = topic {
+        return help_generator(t_type, action);
+    }
 
-    Writer::new(header, template).run_action()?;
+    print!("{}", topic.usage()?);
 
     Ok(())
 }
 
 fn main() {
-    let (named, mapped, _) = get_arguments(vec!["type", "action", "name"]);
+    let arguments = get_arguments();
+    let error_format = get_error_format(&arguments);
+    let result = parse_arguments(arguments).and_then(|invocation| match invocation.command {
+        Command::Generate(arguments) => generate(arguments),
+        Command::List => list(),
+        Command::Help(topic) => help(topic),
+        Command::Init => config::init(),
+        Command::Check => check(),
+    });
 
-    match generate(named, mapped) {
-        Ok(_) => {}
-        Err(error) => {
-            eprintln!("{}", &error);
-        }
+    if let Err(error) = result {
+        error.print(error_format);
+        process::exit(error.kind().exit_code());
     }
 }
@@ -0,0 +1,483 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::console::prompt_parameter;
+use crate::rustgen_error::{ErrorKind, RustgenError, RustgenResult};
+
+/// The file names (in the order they are looked up) of the optional generator metadata file
+pub const META_FILE_NAMES: [&str; 2] = ["_meta.yml", "_meta.yaml"];
+
+/// The folder of a generator whose files are copied byte-for-byte (see [StaticFiles])
+pub const STATIC_DIRECTORY: &str = "_static";
+
+/// The extensions of files which are copied instead of rendered, if the metadata sets none
+const DEFAULT_STATIC_EXTENSIONS: [&str; 20] = [
+    "png", "jpg", "jpeg", "gif", "bmp", "ico", "webp", "svgz", "woff", "woff2", "ttf", "otf",
+    "eot", "pdf", "zip", "gz", "jar", "wasm", "mp3", "mp4",
+];
+
+/// Optional metadata of a generator, read from `{type}/{action}/_meta.yml`
+///
+/// # Example
+///
+/// ```yaml
+/// description: Creates a new controller and registers it
+/// order: [controller.hbs, register.hbs]
+/// parameters:
+///   - name: extension
+///     type: string
+///     description: The file extension of the controller
+///     default: mjs
+///     values: [mjs, js]
+///   - name: subdirectory
+///     description: Where to put the controller (relative to app/controller)
+///     required: true
+///     pattern: "^[a-z_/]+$"
+///   - name: with_tests
+///     type: bool
+///     default: "false"
+/// static:
+///   destination: "public/{{kebab_case name}}"
+///   extensions: [png, ttf]
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct GeneratorMeta {
+    /// What the generator does, shown by `rustgen list` and `rustgen help`
+    #[serde(default)]
+    pub description: Option<String>,
+
+    /// The parameters (`--name=value`) the generator reads
+    #[serde(default)]
+    pub parameters: Vec<GeneratorParameter>,
+
+    /// Templates (paths relative to the generator) which run first, in this order. All other
+    /// templates run after them, sorted by name (numeric prefixes like `2_` sort before `10_`)
+    #[serde(default)]
+    pub order: Vec<String>,
+
+    /// Which files are copied instead of rendered and where they are copied to
+    #[serde(default, rename = "static")]
+    pub static_files: StaticFiles,
+
+    /// Turns the generator into a scaffolding generator (see [Scaffold])
+    #[serde(default)]
+    pub scaffold: Option<Scaffold>,
+}
+
+/// A scaffolding generator mirrors its whole directory tree into the destination (like
+/// cargo-generate), instead of only rendering the templates at its top level.
+///
+/// - Directory and file names can contain handlebars expressions
+///   (e.g. `src/{{snake_case name}}/mod.rs.hbs`), a name rendering to an empty string skips the
+///   file (e.g. `{{#if with_tests}}tests{{/if}}/`)
+/// - A `.hbs` extension is removed from the file name
+/// - The path of the templates defaults to the mirrored path
+/// - Files with a static extension are copied byte-for-byte (see [StaticFiles])
+/// - Files and directories starting with an underscore are skipped at any depth, the files of
+///   `_static/` are copied into the destination as well
+///
+/// # Example
+///
+/// ```yaml
+/// scaffold:
+///   destination: "{{kebab_case name}}"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Scaffold {
+    /// The folder (relative to the project) the tree is mirrored to, default=the project
+    #[serde(default)]
+    pub destination: String,
+}
+
+/// Files of a generator which are copied byte-for-byte (images, fonts, ...) instead of being
+/// rendered as templates: all files in the `_static/` folder (keeping their relative path) and the
+/// files in the generator folder with one of the extensions.
+///
+/// The destination and the file names can contain handlebars expressions, so
+/// `_static/{{name}}/logo.png` is copied to `{destination}/my-name/logo.png`.
+///
+/// # Options
+///
+/// | Name | Description | Default |
+/// | --- | --- | --- |
+/// | `destination` | The folder (relative to the project) the files are copied to | *The project* (the scaffold destination for scaffolding generators) |
+/// | `extensions` | Files in the generator folder with these extensions are copied | Images, fonts, archives, ... |
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StaticFiles {
+    #[serde(default)]
+    pub destination: String,
+
+    #[serde(default = "default_static_extensions")]
+    pub extensions: Vec<String>,
+}
+
+/// A declared parameter of a generator
+///
+/// # Options
+///
+/// | Name | Description | Default |
+/// | --- | --- | --- |
+/// | `name` | Name of the parameter (`--name=value` on the command line) | *Required* |
+/// | `type` | One of `string`, `bool`, `number` or `list` (comma separated values) | `string` |
+/// | `description` | Shown in the help of the generator | *None* |
+/// | `default` | Used if the parameter is neither given nor set in the config defaults | *None* |
+/// | `required` | Fails the generation if the parameter is missing and has no default | `false` |
+/// | `values` | The allowed values (for lists: the allowed items) | *Any* |
+/// | `pattern` | A regex every value (for lists: every item) has to match | *None* |
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GeneratorParameter {
+    pub name: String,
+
+    #[serde(default, rename = "type")]
+    pub parameter_type: ParameterType,
+
+    #[serde(default)]
+    pub description: Option<String>,
+
+    #[serde(default)]
+    pub default: Option<String>,
+
+    #[serde(default)]
+    pub required: bool,
+
+    #[serde(default)]
+    pub values: Vec<String>,
+
+    #[serde(default)]
+    pub pattern: Option<String>,
+}
+
+/// The type of a parameter. As all template data are strings, the types define how a value is
+/// validated and normalized
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ParameterType {
+    /// Any text
+    #[default]
+    String,
+
+    /// `true`/`yes`/`1` become `"true"`, `false`/`no`/`0` become an empty string, so
+    /// `{{#if parameter}}` works as expected in the template
+    Bool,
+
+    /// An integer or floating point number
+    Number,
+
+    /// Comma separated values (`--items=a,b,c`), every item is validated
+    List,
+}
+
+impl GeneratorMeta {
+    /// Reads the metadata file from the generator directory, a missing file results in empty metadata
+    pub fn read(generator_path: &Path) -> RustgenResult<Self> {
+        let path = META_FILE_NAMES
+            .iter()
+            .map(|name| generator_path.join(name))
+            .find(|path| path.is_file());
+
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+
+        serde_yaml::from_str(&fs::read_to_string(&path)?).map_err(|error| {
+            RustgenError::new(
+                ErrorKind::Config,
+                format!(
+                    "Invalid generator metadata in {}: {}",
+                    path.display(),
+                    error
+                ),
+            )
+            .with_source(error)
+        })
+    }
+
+    /// Fills in missing parameters, validates and normalizes all declared parameters
+    ///
+    /// Missing parameters are asked for if `interactive` is set, otherwise their default is used.
+    pub fn apply(
+        &self,
+        data: &mut BTreeMap<String, String>,
+        interactive: bool,
+    ) -> RustgenResult<()> {
+        for parameter in &self.parameters {
+            if !data.contains_key(&parameter.name) {
+                match &parameter.default {
+                    _ if interactive => {
+                        data.insert(parameter.name.clone(), prompt_parameter(parameter)?);
+                    }
+                    Some(default) => {
+                        data.insert(parameter.name.clone(), default.clone());
+                    }
+                    None if parameter.required => {
+                        return Err(RustgenError::new(
+                            ErrorKind::Usage,
+                            format!(
+                                "Missing required parameter --{}=<{}>. Rustgen only asks for \
+                                missing parameters in an interactive terminal (not in a CI or \
+                                with --no-interactive)",
+                                parameter.name,
+                                parameter.parameter_type.name()
+                            ),
+                        ));
+                    }
+                    None => continue,
+                }
+            }
+
+            if let Some(value) = data.get_mut(&parameter.name) {
+                *value = parameter.validate(value)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for StaticFiles {
+    fn default() -> Self {
+        Self {
+            destination: String::new(),
+            extensions: default_static_extensions(),
+        }
+    }
+}
+
+impl GeneratorMeta {
+    /// The path template of a file of the `_static/` folder, `relative` is the path relative to
+    /// it. Scaffolding generators copy them into their destination if no other is set
+    pub fn static_destination(&self, relative: &Path) -> String {
+        match &self.scaffold {
+            Some(scaffold) if self.static_files.destination.is_empty() => {
+                join_destination(&scaffold.destination, relative)
+            }
+            _ => self.static_files.destination(relative),
+        }
+    }
+}
+
+impl StaticFiles {
+    /// Whether the file in the generator folder is copied instead of rendered
+    pub fn is_static(&self, path: &Path) -> bool {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        self.extensions.iter().any(|static_extension| {
+            static_extension
+                .trim_start_matches('.')
+                .eq_ignore_ascii_case(&extension)
+        })
+    }
+
+    /// The path template of the copied file, `relative` is the path relative to the generator or
+    /// `_static/` folder
+    pub fn destination(&self, relative: &Path) -> String {
+        join_destination(&self.destination, relative)
+    }
+}
+
+impl Scaffold {
+    /// The path template of a file of the generator, `relative` is the path relative to the
+    /// generator folder
+    pub fn destination(&self, relative: &Path) -> String {
+        let path = join_destination(&self.destination, relative);
+
+        match path.strip_suffix(".hbs") {
+            Some(path) => path.to_string(),
+            None => path,
+        }
+    }
+}
+
+fn join_destination(destination: &str, relative: &Path) -> String {
+    let relative = relative.to_string_lossy().replace('\\', "/");
+
+    match destination.trim_end_matches('/') {
+        "" => relative,
+        destination => format!("{}/{}", destination, relative),
+    }
+}
+
+fn default_static_extensions() -> Vec<String> {
+    DEFAULT_STATIC_EXTENSIONS
+        .iter()
+        .map(|extension| extension.to_string())
+        .collect()
+}
+
+impl GeneratorParameter {
+    /// Validates the value and returns it normalized (see [ParameterType])
+    pub fn validate(&self, value: &str) -> RustgenResult<String> {
+        match self.parameter_type {
+            ParameterType::String => self.validate_item(value).map(|_| value.to_string()),
+            ParameterType::Bool => match value.to_lowercase().as_str() {
+                "true" | "yes" | "1" => Ok(String::from("true")),
+                "false" | "no" | "0" | "" => Ok(String::new()),
+                _ => Err(self.invalid(value, "expected true or false")),
+            },
+            ParameterType::Number => {
+                value
+                    .parse::<f64>()
+                    .map_err(|_| self.invalid(value, "expected a number"))?;
+
+                self.validate_item(value).map(|_| value.to_string())
+            }
+            ParameterType::List => {
+                let items: Vec<&str> = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .collect();
+
+                for item in &items {
+                    self.validate_item(item)?;
+                }
+
+                Ok(items.join(","))
+            }
+        }
+    }
+
+    fn validate_item(&self, item: &str) -> RustgenResult<()> {
+        if !self.values.is_empty() && !self.values.iter().any(|value| value == item) {
+            return Err(self.invalid(item, format!("expected one of: {}", self.values.join(", "))));
+        }
+
+        if let Some(pattern) = &self.pattern {
+            let regex = Regex::new(pattern).map_err(|error| {
+                RustgenError::new(
+                    ErrorKind::Config,
+                    format!("Invalid pattern for parameter {}: {}", self.name, error),
+                )
+                .with_source(error)
+            })?;
+
+            if !regex.is_match(item) {
+                return Err(self.invalid(item, format!("expected to match {}", pattern)));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn invalid(&self, value: &str, reason: impl ToString) -> RustgenError {
+        RustgenError::new(
+            ErrorKind::Usage,
+            format!(
+                "Invalid value {:?} for parameter --{}: {}",
+                value,
+                self.name,
+                reason.to_string()
+            ),
+        )
+    }
+}
+
+impl ParameterType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParameterType::String => "string",
+            ParameterType::Bool => "bool",
+            ParameterType::Number => "number",
+            ParameterType::List => "list",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameter(yaml: &str) -> GeneratorParameter {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_validate_normalizes_types() {
+        let bool_parameter = parameter("name: flag\ntype: bool");
+        let number = parameter("name: count\ntype: number");
+        let list = parameter("name: items\ntype: list");
+
+        for (value, expected) in [("yes", "true"), ("TRUE", "true"), ("0", ""), ("no", "")] {
+            assert_eq!(bool_parameter.validate(value).unwrap(), expected);
+        }
+
+        assert!(bool_parameter.validate("maybe").is_err());
+        assert_eq!(number.validate("1.5").unwrap(), "1.5");
+        assert!(number.validate("one").is_err());
+        assert_eq!(list.validate(" a, b ,,c ").unwrap(), "a,b,c");
+    }
+
+    #[test]
+    fn test_validate_values_and_pattern() {
+        let values = parameter("name: extension\nvalues: [mjs, js]");
+        let pattern = parameter("name: path\npattern: \"^[a-z_/]+$\"");
+        let list = parameter("name: items\ntype: list\nvalues: [a, b]");
+
+        assert_eq!(values.validate("js").unwrap(), "js");
+        assert_eq!(values.validate("ts").unwrap_err().kind(), ErrorKind::Usage);
+        assert!(pattern.validate("app/controller").is_ok());
+        assert!(pattern.validate("App").is_err());
+        assert_eq!(list.validate("a,b").unwrap(), "a,b");
+        assert!(list.validate("a,c").is_err());
+        assert_eq!(
+            parameter("name: x\npattern: \"(\"")
+                .validate("x")
+                .unwrap_err()
+                .kind(),
+            ErrorKind::Config
+        );
+    }
+
+    #[test]
+    fn test_static_files() {
+        let static_files = StaticFiles {
+            destination: String::from("public/{{name}}/"),
+            extensions: vec![String::from(".PNG"), String::from("ttf")],
+        };
+
+        assert!(static_files.is_static(Path::new("logo.png")));
+        assert!(!static_files.is_static(Path::new("logo.png.hbs")));
+        assert_eq!(
+            static_files.destination(Path::new("fonts/{{name}}.ttf")),
+            "public/{{name}}/fonts/{{name}}.ttf"
+        );
+        assert_eq!(
+            StaticFiles::default().destination(Path::new("logo.png")),
+            "logo.png"
+        );
+    }
+
+    #[test]
+    fn test_scaffold_destination() {
+        let scaffold = Scaffold {
+            destination: String::from("{{name}}"),
+        };
+
+        assert_eq!(
+            scaffold.destination(Path::new("src/{{name}}.rs.hbs")),
+            "{{name}}/src/{{name}}.rs"
+        );
+        assert_eq!(
+            scaffold.destination(Path::new("logo.png")),
+            "{{name}}/logo.png"
+        );
+
+        let meta = GeneratorMeta {
+            scaffold: Some(scaffold),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            meta.static_destination(Path::new("s.txt.hbs")),
+            "{{name}}/s.txt.hbs"
+        );
+    }
+}
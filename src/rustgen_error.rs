@@ -1,26 +1,155 @@
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
 use std::{fmt, io};
 
-use handlebars::TemplateRenderError;
+use handlebars::{TemplateError, TemplateRenderError};
+use serde::Serialize;
 
 pub type RustgenResult<T> = Result<T, RustgenError>;
 
+/// The kind of an error. Every kind exits the application with its own code
+///
+/// # Exit codes
+///
+/// | Kind | Code | Description |
+/// | --- | --- | --- |
+/// | `usage` | 2 | Invalid command line (unknown flags, missing arguments, invalid parameters, unknown generators) |
+/// | `config` | 3 | The .rustgenrc.yml or template directory is invalid |
+/// | `template-parse` | 4 | A template has an invalid handlebars syntax |
+/// | `render` | 5 | A template could not be rendered (e.g. a failing helper) |
+/// | `header-yaml` | 6 | The (rendered) header of a template is no valid yaml |
+/// | `missing-marker` | 7 | A marker for appending could not be found in the target file (or the target rust file could not be parsed) |
+/// | `io` | 8 | A file could not be read or written |
+/// | `conflict` | 9 | A file which should be created already exists |
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorKind {
+    Usage,
+    Config,
+    TemplateParse,
+    Render,
+    HeaderYaml,
+    MissingMarker,
+    Io,
+    Conflict,
+}
+
+/// How errors are printed, selected by `--error-format=text|json`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// The plain error message
+    Text,
+
+    /// A json object containing the kind, exit code, message and causes
+    Json,
+}
+
 pub struct RustgenError {
+    kind: ErrorKind,
     message: String,
     debug: String,
+    source: Option<Box<dyn Error + 'static>>,
+}
+
+/// The json representation of an error (`--error-format=json`)
+#[derive(Serialize)]
+struct JsonError<'a> {
+    kind: ErrorKind,
+    code: i32,
+    message: &'a str,
+    causes: Vec<String>,
+}
+
+impl ErrorKind {
+    /// The exit code of the application when failing with this kind of error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ErrorKind::Usage => 2,
+            ErrorKind::Config => 3,
+            ErrorKind::TemplateParse => 4,
+            ErrorKind::Render => 5,
+            ErrorKind::HeaderYaml => 6,
+            ErrorKind::MissingMarker => 7,
+            ErrorKind::Io => 8,
+            ErrorKind::Conflict => 9,
+        }
+    }
+}
+
+impl FromStr for ErrorFormat {
+    type Err = RustgenError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(RustgenError::new(
+                ErrorKind::Usage,
+                format!(
+                    "Unknown error format '{}'. Expected 'text' or 'json'",
+                    value
+                ),
+            )),
+        }
+    }
 }
 
 impl RustgenError {
-    pub fn new(message: impl ToString) -> Self {
+    pub fn new(kind: ErrorKind, message: impl ToString) -> Self {
         Self {
+            kind,
             debug: message.to_string(),
             message: message.to_string(),
+            source: None,
         }
     }
+
+    /// Attaches the error which caused this one (available through [Error::source])
+    pub fn with_source(mut self, source: impl Error + 'static) -> Self {
+        self.debug = format!("{} ({:?})", self.debug, source);
+        self.source = Some(Box::new(source));
+
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Prints the error to stderr in the given format
+    pub fn print(&self, format: ErrorFormat) {
+        match format {
+            ErrorFormat::Text => eprintln!("{}", self),
+            ErrorFormat::Json => eprintln!("{}", self.to_json()),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let mut causes = vec![];
+        let mut source = self.source();
+
+        while let Some(error) = source {
+            causes.push(error.to_string());
+            source = error.source();
+        }
+
+        let json = JsonError {
+            kind: self.kind,
+            code: self.kind.exit_code(),
+            message: &self.message,
+            causes,
+        };
+
+        serde_json::to_string(&json).unwrap_or_else(|_| format!("{:?}", self.message))
+    }
 }
 
-impl Error for RustgenError {}
+impl Error for RustgenError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_deref()
+    }
+}
 
 impl Display for RustgenError {
     fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
@@ -36,31 +165,116 @@ impl Debug for RustgenError {
 
 impl From<serde_yaml::Error> for RustgenError {
     fn from(parent: serde_yaml::Error) -> Self {
-        Self {
-            message: format!("Could not (de-)serialize: {}", parent),
-            debug: format!("{:?}", parent),
-        }
+        Self::new(
+            ErrorKind::HeaderYaml,
+            format!("Could not (de-)serialize: {}", parent),
+        )
+        .with_source(parent)
     }
 }
 
 impl From<io::Error> for RustgenError {
     fn from(parent: io::Error) -> Self {
-        Self {
-            message: format!(
+        Self::new(
+            ErrorKind::Io,
+            format!(
                 "Could not fulfill file operation: {}\n Error: {:?}",
                 parent,
                 parent.kind()
             ),
-            debug: format!("{:?}", parent),
-        }
+        )
+        .with_source(parent)
     }
 }
 
 impl From<TemplateRenderError> for RustgenError {
     fn from(parent: TemplateRenderError) -> Self {
-        Self {
-            message: format!("Could not render template: {}", parent),
-            debug: format!("{:?}", parent),
-        }
+        let kind = match &parent {
+            TemplateRenderError::TemplateError(_) => ErrorKind::TemplateParse,
+            TemplateRenderError::RenderError(_) => ErrorKind::Render,
+            TemplateRenderError::IOError(_, _) => ErrorKind::Io,
+        };
+
+        Self::new(kind, format!("Could not render template: {}", parent)).with_source(parent)
+    }
+}
+
+impl From<TemplateError> for RustgenError {
+    fn from(parent: TemplateError) -> Self {
+        Self::new(
+            ErrorKind::TemplateParse,
+            format!("Could not parse template: {}", parent),
+        )
+        .with_source(parent)
+    }
+}
+
+impl From<dialoguer::Error> for RustgenError {
+    fn from(parent: dialoguer::Error) -> Self {
+        Self::new(ErrorKind::Io, format!("Could not read input: {}", parent)).with_source(parent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use handlebars::Handlebars;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_exit_codes() {
+        let kinds = [
+            ErrorKind::Usage,
+            ErrorKind::Config,
+            ErrorKind::TemplateParse,
+            ErrorKind::Render,
+            ErrorKind::HeaderYaml,
+            ErrorKind::MissingMarker,
+            ErrorKind::Io,
+            ErrorKind::Conflict,
+        ];
+        let codes: Vec<i32> = kinds.iter().map(ErrorKind::exit_code).collect();
+
+        assert_eq!(codes, [2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(codes.iter().collect::<BTreeSet<_>>().len(), kinds.len());
+    }
+
+    #[test]
+    fn test_kind_of_template_errors() {
+        let mut bars = Handlebars::new();
+        let parse: RustgenError = bars
+            .render_template("{{#if}}", &json!({}))
+            .unwrap_err()
+            .into();
+
+        bars.set_strict_mode(true);
+
+        let render: RustgenError = bars
+            .render_template("{{missing}}", &json!({}))
+            .unwrap_err()
+            .into();
+
+        assert_eq!(parse.kind(), ErrorKind::TemplateParse);
+        assert_eq!(render.kind(), ErrorKind::Render);
+    }
+
+    #[test]
+    fn test_json_contains_causes() {
+        let error = RustgenError::new(ErrorKind::Config, "outer").with_source(
+            RustgenError::new(ErrorKind::Io, "middle").with_source(io::Error::other("inner")),
+        );
+        let json: Value = serde_json::from_str(&error.to_json()).unwrap();
+
+        assert_eq!(
+            json,
+            json!({
+                "kind": "config",
+                "code": 3,
+                "message": "outer",
+                "causes": ["middle", "inner"],
+            })
+        );
     }
 }
@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use handlebars::Handlebars;
+
+use crate::rustgen_error::{ErrorKind, RustgenError, RustgenResult};
+use crate::template::helpers::add_helpers;
+use crate::template::writer::new_path;
+use crate::template::{Asset, ConflictPolicy, Workspace, WriteOptions};
+
+impl Asset {
+    pub fn new(source: PathBuf, path: String, options: WriteOptions) -> Self {
+        Self {
+            source,
+            path,
+            options,
+        }
+    }
+
+    /// Stages the copy of the file, existing files are handled by the conflict policy of the
+    /// config (unchanged files are skipped)
+    pub fn copy(&self, workspace: &mut Workspace) -> RustgenResult<()> {
+        let content = fs::read(&self.source).map_err(|error| {
+            RustgenError::new(
+                ErrorKind::Io,
+                format!("Could not read {}", self.source.display()),
+            )
+            .with_source(error)
+        })?;
+        let mut path = self.path.clone();
+
+        if let Some(existing) = workspace.read_bytes(&path)? {
+            if existing == content {
+                println!("Skipping {}, the file is unchanged", path);
+
+                return Ok(());
+            }
+
+            match self.options.conflict_policy(None, &path)? {
+                ConflictPolicy::Overwrite => {}
+                ConflictPolicy::New => path = new_path(workspace, &path)?,
+                ConflictPolicy::Skip => {
+                    println!("Skipping {}, the file already exists", path);
+
+                    return Ok(());
+                }
+                ConflictPolicy::Fail | ConflictPolicy::Prompt => {
+                    return Err(RustgenError::new(
+                        ErrorKind::Conflict,
+                        format!(
+                            "The file {} already exists. Use --force to overwrite it or set \
+                            on_conflict (skip, overwrite, prompt, new) in the .rustgenrc.yml",
+                            path
+                        ),
+                    ))
+                }
+            }
+        }
+
+        workspace.write_bytes(&path, content)
+    }
+}
+
+/// Renders the handlebars expressions of a path (e.g. `assets/{{kebab_case name}}.png`)
+pub fn render_path(path: &str, data: &BTreeMap<String, String>) -> RustgenResult<String> {
+    let mut bars = Handlebars::new();
+
+    add_helpers(&mut bars);
+    bars.register_escape_fn(handlebars::no_escape);
+
+    Ok(bars.render_template(path, data)?)
+}
+
+/// Renders the path like [render_path], but returns None if a directory or file name renders to
+/// an empty string (so conditional names like `{{#if with_tests}}tests{{/if}}` skip the file)
+pub fn render_optional_path(
+    path: &str,
+    data: &BTreeMap<String, String>,
+) -> RustgenResult<Option<String>> {
+    let rendered = render_path(path, data)?;
+
+    match rendered.split('/').any(|name| name.trim().is_empty()) {
+        true => Ok(None),
+        false => Ok(Some(rendered)),
+    }
+}
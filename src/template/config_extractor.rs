@@ -1,14 +1,63 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
 
-use handlebars::Handlebars;
+use handlebars::{Handlebars, Template};
 
-use crate::rustgen_error::RustgenResult;
+use crate::rustgen_error::{ErrorKind, RustgenError, RustgenResult};
 use crate::template::helpers::add_helpers;
-use crate::template::{PreProcessor, TemplateHeader, WriteAction};
+use crate::template::variables::collect_variables;
+use crate::template::{
+    FileMode, Marker, MarkerDefinition, ModeDefinition, PreProcessor, TemplateHeader,
+};
 
-impl Default for WriteAction {
-    fn default() -> Self {
-        Self::CreateFile
+impl From<MarkerDefinition> for Marker {
+    fn from(definition: MarkerDefinition) -> Self {
+        match definition {
+            MarkerDefinition::Text(text) => Marker {
+                text,
+                occurrence: Default::default(),
+            },
+            MarkerDefinition::Options { marker, occurrence } => Marker {
+                text: marker,
+                occurrence,
+            },
+        }
+    }
+}
+
+impl From<Marker> for MarkerDefinition {
+    fn from(marker: Marker) -> Self {
+        MarkerDefinition::Options {
+            marker: marker.text,
+            occurrence: marker.occurrence,
+        }
+    }
+}
+
+impl TryFrom<ModeDefinition> for FileMode {
+    type Error = String;
+
+    fn try_from(definition: ModeDefinition) -> Result<Self, Self::Error> {
+        // The digits of a number are octal as well (yaml reads an unquoted 755 as number)
+        let digits = match definition {
+            ModeDefinition::Text(text) => text,
+            ModeDefinition::Number(number) => number.to_string(),
+        };
+        let digits = digits.trim().trim_start_matches("0o");
+
+        match u32::from_str_radix(digits, 8) {
+            Ok(mode) if mode <= 0o7777 => Ok(FileMode(mode)),
+            _ => Err(format!(
+                "invalid file mode {:?}, expected octal digits like 0755",
+                digits
+            )),
+        }
+    }
+}
+
+impl From<FileMode> for ModeDefinition {
+    fn from(mode: FileMode) -> Self {
+        ModeDefinition::Text(format!("{:04o}", mode.0))
     }
 }
 
@@ -16,7 +65,17 @@ const MARK_SYMBOL: &str = "---";
 
 impl PreProcessor {
     pub fn new(template: String) -> RustgenResult<Self> {
-        Ok(Self { template })
+        Ok(Self {
+            template,
+            path: None,
+        })
+    }
+
+    /// Sets the path used if the header has none (or the template has no header at all)
+    pub fn with_path(mut self, path: Option<String>) -> Self {
+        self.path = path;
+
+        self
     }
 
     ///
@@ -33,36 +92,249 @@ impl PreProcessor {
 
         add_helpers(&mut bars);
 
-        let (yaml, template) = self.extract_parts();
-        let yaml_rendered = bars.render_template(&yaml, &data)?;
+        let (yaml, template) = self.extract_parts()?;
         let template = bars.render_template(&template, &data)?;
-        let header: TemplateHeader = serde_yaml::from_str(yaml_rendered.as_str())?;
+        let header = match self.has_header() {
+            true => parse_header(&bars.render_template(&header_lines(&yaml), &data)?)?,
+            false => TemplateHeader::default(),
+        };
+
+        Ok((self.complete_header(header)?, template))
+    }
+
+    ///
+    /// Renders only the header with the given data for showing it in the listing/help output.
+    /// The rendering is strict, so if the header needs a variable which is not in the data (like
+    /// the `name`), the raw header is returned instead.
+    ///
+    pub fn preview_header(&self, data: &BTreeMap<String, String>) -> RustgenResult<TemplateHeader> {
+        let mut bars = Handlebars::new();
+
+        add_helpers(&mut bars);
+        bars.set_strict_mode(true);
+
+        if !self.has_header() {
+            return self.complete_header(TemplateHeader::default());
+        }
+
+        let yaml = header_lines(&self.extract_parts()?.0);
+        let rendered = bars
+            .render_template(&yaml, data)
+            .ok()
+            .and_then(|yaml| parse_header(&yaml).ok());
+
+        match rendered {
+            Some(header) => self.complete_header(header),
+            None => self.complete_header(parse_header(&yaml)?),
+        }
+    }
+
+    /// Whether the template starts with a yaml header (a line containing only `---`)
+    fn has_header(&self) -> bool {
+        self.content()
+            .lines()
+            .next()
+            .is_some_and(|line| line.trim_end() == MARK_SYMBOL)
+    }
+
+    /// The template without a byte order mark
+    fn content(&self) -> &str {
+        self.template
+            .strip_prefix('\u{feff}')
+            .unwrap_or(&self.template)
+    }
+
+    /// Falls back to the path of the preprocessor if the header sets none
+    fn complete_header(&self, mut header: TemplateHeader) -> RustgenResult<TemplateHeader> {
+        if header.path.is_empty() {
+            header.path = self.path.clone().ok_or_else(|| {
+                RustgenError::new(ErrorKind::HeaderYaml, "The template header has no path")
+            })?;
+        }
+
+        Ok(header)
+    }
+
+    ///
+    /// Returns the names of all variables used in the header, template and default path
+    ///
+    pub fn variables(&self) -> RustgenResult<BTreeSet<String>> {
+        let mut bars = Handlebars::new();
+
+        add_helpers(&mut bars);
+
+        let (yaml, template) = self.extract_parts()?;
+        let mut variables = collect_variables(&Template::compile(&yaml)?, &bars);
+        variables.append(&mut collect_variables(
+            &Template::compile(&template)?,
+            &bars,
+        ));
 
-        Ok((header, template))
+        if let Some(path) = &self.path {
+            variables.append(&mut collect_variables(&Template::compile(path)?, &bars));
+        }
+
+        Ok(variables)
     }
 
     ///
-    /// Extracts the template and header part from the template property
+    /// Extracts the template and header part from the template property. The header is enclosed
+    /// by two lines containing only `---`, the first one has to be the first line of the template.
+    /// Templates without a header return an empty header and the unchanged template
     ///
     /// # Return
     ///
     /// - .0 - The extracted header String
     /// - .1 - The remaining String (template)
     ///
-    fn extract_parts(&self) -> (String, String) {
-        let mut template = self.template.clone();
-        let header_yaml_start = template.find(MARK_SYMBOL).unwrap_or(0) + MARK_SYMBOL.len();
-        let mut header_yaml: String = template.chars().skip(header_yaml_start).collect();
-        let header_yaml_end = header_yaml.find(MARK_SYMBOL).unwrap_or(0);
-        header_yaml = header_yaml.chars().take(header_yaml_end).collect();
-
-        template = template
-            .chars()
-            .skip(header_yaml_end + MARK_SYMBOL.len() + header_yaml_start)
-            .collect();
-        template = String::from(template.trim_start());
-        template = String::from(template.trim_end());
-
-        (header_yaml, template)
+    pub fn extract_parts(&self) -> RustgenResult<(String, String)> {
+        let content = self.content();
+
+        if !self.has_header() {
+            return Ok((String::new(), content.to_string()));
+        }
+
+        let mut lines = content.split_inclusive('\n');
+        let header_start = lines.next().map(str::len).unwrap_or_default();
+        let mut offset = header_start;
+
+        for line in lines {
+            if line.trim_end() == MARK_SYMBOL {
+                let header = &content[header_start..offset];
+                let mut template = &content[offset + line.len()..];
+
+                // Only blank lines are removed, the indentation of the first line is kept
+                while let Some(rest) = template
+                    .strip_prefix('\n')
+                    .or_else(|| template.strip_prefix("\r\n"))
+                {
+                    template = rest;
+                }
+
+                return Ok((
+                    header.to_string(),
+                    template.trim_end_matches(['\r', '\n']).to_string(),
+                ));
+            }
+
+            offset += line.len();
+        }
+
+        Err(RustgenError::new(
+            ErrorKind::HeaderYaml,
+            "The template header is not closed, a line containing only --- is missing after \
+            the header",
+        ))
+    }
+}
+
+/// Puts the header on the lines it has in the template (after the opening `---`), so the line
+/// numbers of errors match the template
+fn header_lines(yaml: &str) -> String {
+    format!("\n{}", yaml)
+}
+
+fn parse_header(yaml: &str) -> RustgenResult<TemplateHeader> {
+    serde_yaml::from_str(yaml).map_err(|error| {
+        let message = match error.location() {
+            Some(location) => {
+                // The location is moved to the front, so it is not repeated at the end
+                let suffix = format!(" at line {} column {}", location.line(), location.column());
+                let description = error.to_string();
+
+                format!(
+                    "Invalid template header at line {}, column {}: {}",
+                    location.line(),
+                    location.column(),
+                    description.strip_suffix(&suffix).unwrap_or(&description)
+                )
+            }
+            None => format!("Invalid template header: {}", error),
+        };
+
+        RustgenError::new(ErrorKind::HeaderYaml, message).with_source(error)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_mode_is_octal() {
+        let mode = |yaml: &str| serde_yaml::from_str::<FileMode>(yaml).map(|mode| mode.0);
+
+        assert_eq!(mode("0755").unwrap(), 0o755);
+        assert_eq!(mode("755").unwrap(), 0o755);
+        assert_eq!(mode("\"0o644\"").unwrap(), 0o644);
+        assert_eq!(mode("\"600\"").unwrap(), 0o600);
+        assert!(mode("0799").is_err());
+    }
+
+    #[test]
+    fn test_header_is_optional_with_default_path() {
+        let data = BTreeMap::new();
+        let processor = PreProcessor::new(String::from("content\n"))
+            .unwrap()
+            .with_path(Some(String::from("src/lib.rs")));
+        let (header, template) = processor.extract_config_template(data.clone()).unwrap();
+
+        assert_eq!(header.path, "src/lib.rs");
+        assert_eq!(template, "content\n");
+
+        let processor = PreProcessor::new(String::from("---\nmode: 0755\n---\ncontent")).unwrap();
+
+        assert!(processor.extract_config_template(data).is_err());
+    }
+
+    #[test]
+    fn test_extract_parts() {
+        let processor = PreProcessor::new(String::from(
+            "\u{feff}---\r\npath: ä.md\r\n---\r\n# Größe\n---\nmore\n",
+        ))
+        .unwrap();
+
+        assert_eq!(
+            processor.extract_parts().unwrap(),
+            (
+                String::from("path: ä.md\r\n"),
+                String::from("# Größe\n---\nmore")
+            )
+        );
+        assert_eq!(
+            PreProcessor::new(String::from(
+                "---\npath: a\n---\n\r\n    indented\n\tsecond\n"
+            ))
+            .unwrap()
+            .extract_parts()
+            .unwrap()
+            .1,
+            "    indented\n\tsecond"
+        );
+        assert_eq!(
+            PreProcessor::new(String::from("    indented first line\n\tsecond"))
+                .unwrap()
+                .extract_parts()
+                .unwrap()
+                .1,
+            "    indented first line\n\tsecond"
+        );
+        assert!(PreProcessor::new(String::from("---\npath: a\n"))
+            .unwrap()
+            .extract_parts()
+            .is_err());
+    }
+
+    #[test]
+    fn test_header_errors_have_template_lines() {
+        let processor =
+            PreProcessor::new(String::from("---\npath: a\naction: Nope\n---\n")).unwrap();
+        let error = processor
+            .extract_config_template(BTreeMap::new())
+            .unwrap_err();
+
+        assert!(error
+            .to_string()
+            .starts_with("Invalid template header at line 3, column 9: action: unknown variant"));
     }
 }
@@ -7,7 +7,14 @@ use handlebars::{
 };
 use regex::Regex;
 
-use crate::template::{ConcatHelper, DefaultHelper, RegexReplaceHelper, SetHelper, TimeHelper};
+use crate::template::{
+    CaptureHelper, ConcatHelper, DefaultHelper, RegexReplaceHelper, SetHelper, TimeHelper,
+};
+
+/// Encloses the group name of a [CaptureHelper] in the rendered template (private use characters,
+/// so they never collide with the content), the writer replaces it with the captured text
+pub const CAPTURE_START: char = '\u{e000}';
+pub const CAPTURE_END: char = '\u{e001}';
 
 macro_rules! add_case_helper {
     ($bars: expr, $name: ident, $case: expr) => {
@@ -46,6 +53,7 @@ pub fn add_helpers(bars: &mut Handlebars) {
     bars.register_helper("set", Box::new(SetHelper));
     bars.register_helper("replace", Box::new(replace));
     bars.register_helper("time", Box::new(TimeHelper));
+    bars.register_helper("capture", Box::new(CaptureHelper));
 }
 
 impl HelperDef for RegexReplaceHelper {
@@ -111,11 +119,32 @@ impl HelperDef for ConcatHelper {
         let strings = self.stringify_params(params);
 
         Ok(Some(ScopedJson::Derived(JsonValue::String(
-            strings.join(&String::new()),
+            strings.join(""),
         ))))
     }
 }
 
+impl HelperDef for CaptureHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<Option<ScopedJson<'reg, 'rc>>, RenderError> {
+        let group = match helper.param(0).map(|param| param.value()) {
+            Some(JsonValue::String(name)) => name.clone(),
+            Some(JsonValue::Number(index)) => index.to_string(),
+            _ => return Err(RenderError::new("Missing group argument (index or name)")),
+        };
+
+        Ok(Some(ScopedJson::Derived(JsonValue::String(format!(
+            "{}{}{}",
+            CAPTURE_START, group, CAPTURE_END
+        )))))
+    }
+}
+
 impl HelperDef for DefaultHelper {
     fn call_inner<'reg: 'rc, 'rc>(
         &self,
@@ -126,7 +155,7 @@ impl HelperDef for DefaultHelper {
     ) -> Result<Option<ScopedJson<'reg, 'rc>>, RenderError> {
         let arguments = helper.params();
         let value = arguments
-            .get(0)
+            .first()
             .ok_or(RenderError::new("Missing value argument"))?;
         let fallback = arguments
             .get(1)
@@ -157,7 +186,7 @@ impl HelperDef for SetHelper {
     ) -> Result<Option<ScopedJson<'reg, 'rc>>, RenderError> {
         let arguments = helper.params();
         let variable_name = arguments
-            .get(0)
+            .first()
             .ok_or(RenderError::new("Missing variable_name argument"))?;
         let content = arguments
             .get(1)
@@ -195,7 +224,7 @@ impl HelperDef for TimeHelper {
             None,
             ScopedJson::Derived(JsonValue::String(default_value.clone())),
         );
-        let date_format = arguments.get(0).unwrap_or(&default_path);
+        let date_format = arguments.first().unwrap_or(&default_path);
         let date_format = date_format
             .value()
             .as_str()
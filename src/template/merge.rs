@@ -0,0 +1,425 @@
+use std::path::Path;
+
+use serde_json::ser::PrettyFormatter;
+use serde_json::{Serializer, Value as JsonValue};
+use serde_yaml::Value as YamlValue;
+use toml_edit::{DocumentMut, Item, Table};
+
+use crate::rustgen_error::{ErrorKind, RustgenError, RustgenResult};
+use crate::template::MergeFormat;
+
+impl MergeFormat {
+    /// Detects the format by the extension of the file
+    pub fn from_path(path: &str) -> RustgenResult<Self> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+
+        match extension.to_lowercase().as_str() {
+            "json" => Ok(MergeFormat::Json),
+            "yaml" | "yml" => Ok(MergeFormat::Yaml),
+            "toml" => Ok(MergeFormat::Toml),
+            _ => Err(RustgenError::new(
+                ErrorKind::Config,
+                format!(
+                    "Cannot merge into {}, only .json, .yaml, .yml and .toml files are supported. \
+                    Set the format of the Merge action to merge into other files",
+                    path
+                ),
+            )),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            MergeFormat::Json => "json",
+            MergeFormat::Yaml => "yaml",
+            MergeFormat::Toml => "toml",
+        }
+    }
+}
+
+/// Deep-merges the content into the target (None if the file does not exist yet) at the dot
+/// separated key path and returns the serialized result
+pub fn merge(
+    path: &str,
+    target: Option<&str>,
+    content: &str,
+    key: Option<&str>,
+    format: MergeFormat,
+) -> RustgenResult<String> {
+    let merger = Merger { path, key, format };
+    let target = target.filter(|target| !target.trim().is_empty());
+
+    match format {
+        MergeFormat::Json => merger.merge_json(target, content),
+        MergeFormat::Yaml => merger.merge_yaml(target, content),
+        MergeFormat::Toml => merger.merge_toml(target, content),
+    }
+}
+
+struct Merger<'a> {
+    path: &'a str,
+    key: Option<&'a str>,
+    format: MergeFormat,
+}
+
+impl Merger<'_> {
+    fn merge_json(&self, target: Option<&str>, content: &str) -> RustgenResult<String> {
+        let mut document = match target {
+            Some(target) => {
+                serde_json::from_str(target).map_err(|error| self.invalid_target(error))?
+            }
+            None => JsonValue::Object(Default::default()),
+        };
+        let source = serde_json::from_str(content).map_err(|error| self.invalid_content(error))?;
+
+        let mut node = &mut document;
+
+        for part in self.key_parts() {
+            node = match node {
+                JsonValue::Object(map) => map
+                    .entry(part)
+                    .or_insert_with(|| JsonValue::Object(Default::default())),
+                _ => return Err(self.no_object()),
+            };
+        }
+
+        if !node.is_object() {
+            return Err(self.no_object());
+        }
+
+        merge_json_value(node, source);
+
+        // Keeps the indentation of the existing file
+        let indent = target
+            .and_then(|target| target.lines().find(|line| line.starts_with([' ', '\t'])))
+            .map(|line| &line[..line.len() - line.trim_start().len()])
+            .unwrap_or("  ");
+        let mut output = vec![];
+        let mut serializer = Serializer::with_formatter(
+            &mut output,
+            PrettyFormatter::with_indent(indent.as_bytes()),
+        );
+
+        serde::Serialize::serialize(&document, &mut serializer)
+            .map_err(|error| self.invalid_target(error))?;
+
+        Ok(String::from_utf8_lossy(&output).to_string() + "\n")
+    }
+
+    fn merge_yaml(&self, target: Option<&str>, content: &str) -> RustgenResult<String> {
+        let mut document = match target {
+            Some(target) => {
+                serde_yaml::from_str(target).map_err(|error| self.invalid_target(error))?
+            }
+            None => YamlValue::Mapping(Default::default()),
+        };
+        let source = serde_yaml::from_str(content).map_err(|error| self.invalid_content(error))?;
+
+        let mut node = &mut document;
+
+        for part in self.key_parts() {
+            let key = YamlValue::String(part.to_string());
+
+            node = match node {
+                YamlValue::Mapping(map) => {
+                    if !map.contains_key(&key) {
+                        map.insert(key.clone(), YamlValue::Mapping(Default::default()));
+                    }
+
+                    match map.get_mut(&key) {
+                        Some(node) => node,
+                        None => return Err(self.no_object()),
+                    }
+                }
+                _ => return Err(self.no_object()),
+            };
+        }
+
+        if !node.is_mapping() {
+            return Err(self.no_object());
+        }
+
+        merge_yaml_value(node, source);
+
+        let output =
+            serde_yaml::to_string(&document).map_err(|error| self.invalid_target(error))?;
+
+        // serde_yaml always starts the document with a separator
+        match target.is_some_and(|target| target.starts_with("---")) {
+            true => Ok(output),
+            false => Ok(output.trim_start_matches("---\n").to_string()),
+        }
+    }
+
+    fn merge_toml(&self, target: Option<&str>, content: &str) -> RustgenResult<String> {
+        let mut document: DocumentMut = target
+            .unwrap_or_default()
+            .parse()
+            .map_err(|error| self.invalid_target(error))?;
+        let mut source: DocumentMut = content
+            .parse()
+            .map_err(|error| self.invalid_content(error))?;
+
+        // Tables of the content are appended after the tables of the target
+        let mut last_position = 0;
+        let separate = !document.is_empty();
+
+        visit_toml_tables(&mut document, &mut |table| {
+            last_position = last_position.max(table.position().unwrap_or_default());
+        });
+        visit_toml_tables(&mut source, &mut |table| {
+            if let Some(position) = table.position() {
+                table.set_position(last_position + 1 + position);
+
+                if separate
+                    && table.decor().prefix().is_none_or(|prefix| {
+                        prefix.as_str().is_some_and(|prefix| prefix.is_empty())
+                    })
+                {
+                    table.decor_mut().set_prefix("\n");
+                }
+            }
+        });
+
+        let mut node = document.as_item_mut();
+
+        for part in self.key_parts() {
+            let table = node.as_table_like_mut().ok_or_else(|| self.no_object())?;
+
+            if table.get(part).is_none() {
+                table.insert(part, Item::Table(Table::new()));
+            }
+
+            node = table.get_mut(part).ok_or_else(|| self.no_object())?;
+        }
+
+        if !node.is_table_like() {
+            return Err(self.no_object());
+        }
+
+        merge_toml_item(node, source.as_item().clone());
+
+        Ok(document.to_string())
+    }
+
+    fn key_parts(&self) -> impl Iterator<Item = &str> {
+        self.key
+            .unwrap_or_default()
+            .split('.')
+            .filter(|part| !part.is_empty())
+    }
+
+    fn invalid_target(&self, error: impl std::error::Error + 'static) -> RustgenError {
+        RustgenError::new(
+            ErrorKind::Io,
+            format!(
+                "Could not merge into {}, the file is no valid {}: {}",
+                self.path,
+                self.format.name(),
+                error
+            ),
+        )
+        .with_source(error)
+    }
+
+    fn invalid_content(&self, error: impl std::error::Error + 'static) -> RustgenError {
+        RustgenError::new(
+            ErrorKind::Render,
+            format!(
+                "Could not merge into {}, the rendered template is no valid {}: {}",
+                self.path,
+                self.format.name(),
+                error
+            ),
+        )
+        .with_source(error)
+    }
+
+    fn no_object(&self) -> RustgenError {
+        RustgenError::new(
+            ErrorKind::Config,
+            format!(
+                "Could not merge into {}, the key {:?} is no object/table",
+                self.path,
+                self.key.unwrap_or_default()
+            ),
+        )
+    }
+}
+
+fn merge_json_value(target: &mut JsonValue, source: JsonValue) {
+    match (target, source) {
+        (JsonValue::Object(target), JsonValue::Object(source)) => {
+            for (key, value) in source {
+                match target.get_mut(&key) {
+                    Some(existing) => merge_json_value(existing, value),
+                    None => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (JsonValue::Array(target), JsonValue::Array(source)) => {
+            for value in source {
+                if !target.contains(&value) {
+                    target.push(value);
+                }
+            }
+        }
+        (target, source) => *target = source,
+    }
+}
+
+fn merge_yaml_value(target: &mut YamlValue, source: YamlValue) {
+    match (target, source) {
+        (YamlValue::Mapping(target), YamlValue::Mapping(source)) => {
+            for (key, value) in source {
+                match target.get_mut(&key) {
+                    Some(existing) => merge_yaml_value(existing, value),
+                    None => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (YamlValue::Sequence(target), YamlValue::Sequence(source)) => {
+            for value in source {
+                if !target.contains(&value) {
+                    target.push(value);
+                }
+            }
+        }
+        (target, source) => *target = source,
+    }
+}
+
+/// Calls the callback for every (nested) table of the table
+fn visit_toml_tables(table: &mut Table, callback: &mut impl FnMut(&mut Table)) {
+    for (_, item) in table.iter_mut() {
+        match item {
+            Item::Table(table) => {
+                callback(table);
+                visit_toml_tables(table, callback);
+            }
+            Item::ArrayOfTables(tables) => {
+                for table in tables.iter_mut() {
+                    callback(table);
+                    visit_toml_tables(table, callback);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Merges toml items, replaced values keep their comments and whitespace
+fn merge_toml_item(target: &mut Item, source: Item) {
+    if let (Some(target), Some(source)) = (target.as_table_like_mut(), source.as_table_like()) {
+        for (key, value) in source.iter() {
+            match target.get_mut(key) {
+                Some(existing) => merge_toml_item(existing, value.clone()),
+                None => {
+                    target.insert(key, value.clone());
+                }
+            }
+        }
+
+        return;
+    }
+
+    if let (Some(target), Some(source)) = (target.as_array_mut(), source.as_array()) {
+        for value in source.iter() {
+            let text = value.to_string();
+
+            if !target
+                .iter()
+                .any(|existing| existing.to_string().trim() == text.trim())
+            {
+                target.push_formatted(value.clone());
+            }
+        }
+
+        return;
+    }
+
+    if let (Some(target), Some(source)) =
+        (target.as_array_of_tables_mut(), source.as_array_of_tables())
+    {
+        for table in source.iter() {
+            target.push(table.clone());
+        }
+
+        return;
+    }
+
+    match source.into_value() {
+        Ok(value) => match target.as_value_mut() {
+            Some(existing) => {
+                let decor = existing.decor().clone();
+
+                *existing = value;
+                *existing.decor_mut() = decor;
+            }
+            None => *target = Item::Value(value),
+        },
+        Err(source) => *target = source,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_toml_keeps_comments() {
+        let target =
+            "[package]\nname = \"x\" # the name\n\n[dependencies]\nserde = \"0.9\" # old\n";
+        let content = "serde = \"1.0\"\nregex = \"1\"";
+
+        assert_eq!(
+            merge("Cargo.toml", Some(target), content, Some("dependencies"), MergeFormat::Toml)
+                .unwrap(),
+            "[package]\nname = \"x\" # the name\n\n[dependencies]\nserde = \"1.0\" # old\nregex = \"1\"\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_json_keeps_order() {
+        let target =
+            "{\n    \"name\": \"x\",\n    \"files\": [\"a\"],\n    \"version\": \"1\"\n}\n";
+        let content = "{\"files\": [\"a\", \"b\"], \"version\": \"2\"}";
+
+        assert_eq!(
+            merge("package.json", Some(target), content, None, MergeFormat::Json).unwrap(),
+            "{\n    \"name\": \"x\",\n    \"files\": [\n        \"a\",\n        \"b\"\n    ],\n    \"version\": \"2\"\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_yaml_at_key() {
+        let target = "name: x\nlist:\n  - a\n";
+
+        assert_eq!(
+            merge(
+                "c.yml",
+                Some(target),
+                "deep: true",
+                Some("nested"),
+                MergeFormat::Yaml
+            )
+            .unwrap(),
+            "name: x\nlist:\n  - a\nnested:\n  deep: true\n"
+        );
+        assert!(merge(
+            "c.yml",
+            Some(target),
+            "a: 1",
+            Some("name"),
+            MergeFormat::Yaml
+        )
+        .is_err());
+    }
+}
@@ -1,18 +1,113 @@
+pub mod assets;
 pub mod config_extractor;
 pub mod helpers;
+pub mod merge;
+pub mod rust_code;
+pub mod variables;
+pub mod workspace;
 pub mod writer;
 
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
 /// Prepares the template file
 /// - Splits the yaml head and template content
 #[derive(Debug, Clone)]
 pub struct PreProcessor {
     template: String,
+
+    /// Used if the header sets no path or the template has no header (scaffolding generators)
+    path: Option<String>,
 }
 
 /// Writes the rendered template to the target file (creates a new file/appends to an existing one)
 pub struct Writer {
     header: TemplateHeader,
     rendered_template: String,
+    options: WriteOptions,
+}
+
+/// Copies a static file (image, font, ...) of a generator byte-for-byte to the rendered path
+#[derive(Debug, Clone)]
+pub struct Asset {
+    source: PathBuf,
+    path: String,
+    options: WriteOptions,
+}
+
+/// Invocation wide settings of the [Writer]
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    /// Used for templates without an `on_conflict` header (from the .rustgenrc.yml)
+    pub on_conflict: ConflictPolicy,
+
+    /// `--force`, overwrites existing files regardless of any conflict policy
+    pub force: bool,
+
+    /// Whether the user can be asked (required for [ConflictPolicy::Prompt])
+    pub interactive: bool,
+}
+
+/// In-memory view of all files touched by one invocation
+/// - Reads return the already written content of earlier templates
+/// - Writes are staged and only written to the disk on commit (never in a dry-run), so a failing
+///   template leaves all files untouched
+/// - Keeps the original content of every touched file for showing the diff and rolling back
+/// - Files are edited with `\n` line endings, CRLF line endings and a BOM of the original file
+///   are restored when writing
+/// - Binary files (see [Asset]), symlinks and file modes are staged as well and written after all
+///   text files
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    root: PathBuf,
+    files: BTreeMap<PathBuf, StagedFile>,
+    assets: BTreeMap<PathBuf, StagedAsset>,
+    links: BTreeMap<PathBuf, StagedLink>,
+}
+
+/// A file touched in the [Workspace]
+#[derive(Debug, Clone)]
+struct StagedFile {
+    /// The content before the invocation, None if the file did not exist
+    original: Option<String>,
+
+    /// The content with `\n` line endings and without a BOM, converted back on [Workspace::commit]
+    content: String,
+    format: TextFormat,
+
+    /// The permissions the file gets on commit (unix only), None keeps them
+    mode: Option<u32>,
+
+    /// The permissions before the invocation
+    original_mode: Option<u32>,
+}
+
+/// A binary file copied by an [Asset], written as is
+#[derive(Debug, Clone)]
+struct StagedAsset {
+    /// The content before the invocation, None if the file did not exist
+    original: Option<Vec<u8>>,
+    content: Vec<u8>,
+}
+
+/// A symlink created by the [Workspace]
+#[derive(Debug, Clone)]
+struct StagedLink {
+    /// Where the link points to (relative to the directory of the link)
+    target: PathBuf,
+
+    /// The target of the link which is replaced, None if there was none
+    original: Option<PathBuf>,
+}
+
+/// The line ending style and BOM of a file, kept when the file is written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct TextFormat {
+    /// Most lines end with `\r\n`
+    crlf: bool,
+
+    /// The file starts with an UTF-8 byte order mark
+    bom: bool,
 }
 
 /// Structural use. Shows the available options for the header
@@ -22,6 +117,8 @@ pub struct Writer {
 /// ---
 /// path: src/project/controllers/{{name}}.lua
 /// action: CreateFile
+/// on_conflict: skip
+/// description: Creates the controller class
 /// ---
 /// ```
 ///
@@ -32,27 +129,215 @@ pub struct Writer {
 /// action:
 ///     Append:
 ///         After:  "-- Marker for controller registration"
+/// if_missing: append_end
+/// skip_if: "require('controllers/{{name}}')"
 /// ---
 /// ```
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct TemplateHeader {
-    /// The path where the final file should be put/which file should be replaced
+    /// The path where the final file should be put/which file should be replaced. Defaults to the
+    /// file name of the template without `.hbs` (the mirrored path in scaffolding generators), so
+    /// templates can omit the header
+    #[serde(default)]
     pub path: String,
 
     /// The action that has te be performed, default=CreateFile
     #[serde(default)]
     pub action: WriteAction,
+
+    /// What to do if the file of a CreateFile (or Symlink) action already exists. Falls back to the
+    /// `on_conflict` of the .rustgenrc.yml and then to [ConflictPolicy::Fail]
+    #[serde(default)]
+    pub on_conflict: Option<ConflictPolicy>,
+
+    /// What to do if the marker (or pattern) of an Append, Replace or Delete action is not in the
+    /// file, default=error
+    #[serde(default)]
+    pub if_missing: MissingMarkerPolicy,
+
+    /// How the content of an Append action is indented, default=unchanged
+    #[serde(default)]
+    pub indent: Option<Indent>,
+
+    /// Skips an Append action if the target file already contains the text (or matches the regex)
+    #[serde(default)]
+    pub skip_if: Option<SkipCondition>,
+
+    /// Skips an Append action if the target file already contains the exact rendered content,
+    /// default=false
+    #[serde(default)]
+    pub skip_if_present: bool,
+
+    /// The permissions of the written file on unix (e.g. `0755` for scripts), default=unchanged
+    #[serde(default)]
+    pub mode: Option<FileMode>,
+
+    /// A short description of what the template does, shown by `rustgen list` and `rustgen help`
+    #[serde(default)]
+    pub description: Option<String>,
 }
 
 /// Structural use. Available actions for modifying the source code
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub enum WriteAction {
     /// Creates a new file
+    #[default]
     CreateFile,
 
     /// Appends to an existing file
     /// Can be at different locations/markers in a file
     Append(ExtendLocation),
+
+    /// Replaces the matches of a pattern in an existing file with the content
+    Replace(Pattern),
+
+    /// Deletes lines or a block from an existing file, the content of the template is ignored
+    Delete(DeleteTarget),
+
+    /// Replaces the lines between a begin and an end marker with the content, so the generator
+    /// can be re-run to keep the region up to date
+    Region(Region),
+
+    /// Deep-merges the content into a json, yaml or toml file (the target is created if it does
+    /// not exist)
+    Merge(MergeOptions),
+
+    /// Adds the content to a rust file, the location is found by parsing the file
+    Rust(RustEdit),
+
+    /// Creates a symlink at the path, pointing to the given target (relative to the directory of
+    /// the link). The target has to be inside of the project, the content of the template is
+    /// ignored
+    Symlink(String),
+}
+
+/// Octal unix file permissions
+///
+/// # Example
+/// ```yaml
+/// mode: 0755 # same as 755 or "0o755"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "ModeDefinition", into = "ModeDefinition")]
+pub struct FileMode(pub u32);
+
+/// The (de-)serialized form of a [FileMode]. Yaml reads `0755` as text and `755` as number, both
+/// are octal digits. An unquoted `0o755` is already converted by yaml, so it has to be quoted
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum ModeDefinition {
+    Number(u32),
+    Text(String),
+}
+
+/// Structural use. Syntax aware edits of rust files, the content of the template is parsed as the
+/// added item. Edits are skipped if the file already contains an item with the same name
+///
+/// # Example
+/// ```yaml
+/// action:
+///     Rust: AddMod
+/// ---
+/// pub mod {{snake_case name}};
+/// ```
+///
+/// ```yaml
+/// action:
+///     Rust:
+///         AddMethod: Generator
+/// ---
+/// pub fn {{snake_case name}}(&self) {}
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum RustEdit {
+    /// Adds a `mod x;` declaration after the last one (or the last `use`)
+    AddMod,
+
+    /// Merges a `use` declaration into the existing use tree with the same root (or adds it after
+    /// the last one). The merged declaration is rewritten on one line, so declarations spanning
+    /// multiple lines or containing comments are left as they are and the new one is added after
+    /// the last `use` instead
+    AddUse,
+
+    /// Adds a variant to the enum with the given name
+    AddVariant(String),
+
+    /// Adds an arm to the first `match` in the body of the function (or method) with the given
+    /// name, before a trailing `_` arm. Nested functions and closures are not searched
+    AddMatchArm(String),
+
+    /// Adds a method (or another associated item) to the `impl` block of the given type
+    AddMethod(String),
+}
+
+/// Structural use. Where and how the content of a Merge action is merged
+///
+/// Objects/tables are merged recursively, missing items are added to arrays and all other values
+/// are replaced. The existing order of keys is kept, comments are only kept in toml files.
+///
+/// # Example
+/// ```yaml
+/// path: Cargo.toml
+/// action:
+///     Merge:
+///         key: dependencies
+/// ---
+/// serde = { version = "1.0", features = ["derive"] }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct MergeOptions {
+    /// A dot separated path of the object/table the content is merged into, default=the root
+    #[serde(default)]
+    pub key: Option<String>,
+
+    /// The format of the target and the content, default=detected by the file extension
+    #[serde(default)]
+    pub format: Option<MergeFormat>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+/// Structural use. What happens if a file which should be created already exists
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    /// Fails the generation, naming the existing file
+    #[default]
+    Fail,
+
+    /// Keeps the existing file untouched
+    Skip,
+
+    /// Replaces the existing file
+    Overwrite,
+
+    /// Asks the user what to do (fails if rustgen is not running interactively)
+    Prompt,
+
+    /// Writes the content next to the existing file as `{path}.new` (fails if that exists as well)
+    New,
+}
+
+/// Structural use. What happens if the marker of an Append, Replace or Delete action is not found
+/// in the file
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingMarkerPolicy {
+    /// Fails the generation, naming the file and the marker
+    #[default]
+    Error,
+
+    /// Leaves the file untouched
+    Skip,
+
+    /// Appends the content to the end of the file instead (a Delete action fails)
+    AppendEnd,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -64,10 +349,289 @@ pub enum ExtendLocation {
     EndOfFile,
 
     /// Appends after a given marker (eg. a code comment or a function start)
-    After(String),
+    After(Marker),
 
     /// Appends before a given marker (.eg a class header)
-    Before(String),
+    Before(Marker),
+
+    /// Appends after the match of a regex (eg. the last `use` statement)
+    AfterRegex(RegexMarker),
+
+    /// Appends before the match of a regex (eg. the closing brace of an `impl` block)
+    BeforeRegex(RegexMarker),
+
+    /// Inserts the content as the given line (1 is the first line), negative numbers count from
+    /// the end (-1 is the new last line)
+    AtLine(i64),
+
+    /// Inserts the content a number of lines away from a marker or before the next blank line
+    /// after it
+    Offset(Offset),
+
+    /// Inserts every line of the content in sorted order into the list following a marker,
+    /// lines which are already in the list are skipped
+    Sorted(SortedList),
+}
+
+/// Structural use. A list of lines following the line of a marker
+///
+/// # Example
+/// ```yaml
+/// action:
+///     Append:
+///         Sorted:
+///             marker: "pub enum Command {"
+///             end: "^\\s*}"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SortedList {
+    pub marker: Marker,
+
+    /// A regex matching the first line after the list, default=a blank line. A list also ends
+    /// before the next occurrence of the marker
+    #[serde(default)]
+    pub end: Option<String>,
+}
+
+/// Structural use. An insertion point relative to the line of a marker
+///
+/// # Example
+/// ```yaml
+/// action:
+///     Append:
+///         Offset:
+///             marker: "migrations = ["
+///             lines: 2
+/// ---
+/// action:
+///     Append:
+///         Offset:
+///             marker: "# Migrations"
+///             before_blank_line: true
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Offset {
+    pub marker: Marker,
+
+    /// Moves the insertion point by this number of lines. 0 is directly after the line of the
+    /// marker, -1 directly before it, default=0
+    #[serde(default)]
+    pub lines: i64,
+
+    /// Inserts before the next blank line (or at the end of the file) after the marker instead,
+    /// default=false
+    #[serde(default)]
+    pub before_blank_line: bool,
+}
+
+/// Structural use. A marker in the target file
+///
+/// Can be given as plain text (only the first occurrence is used) or with an occurrence selector
+///
+/// # Example
+/// ```yaml
+/// action:
+///     Append:
+///         After: "-- first occurrence of this marker"
+/// ---
+/// action:
+///     Append:
+///         Before:
+///             marker: "import "
+///             occurrence: last # or first, all, nth: 2
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "MarkerDefinition", into = "MarkerDefinition")]
+pub struct Marker {
+    pub text: String,
+    pub occurrence: Occurrence,
+}
+
+/// The (de-)serialized form of a [Marker]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum MarkerDefinition {
+    Text(String),
+    Options {
+        marker: String,
+        #[serde(default)]
+        occurrence: Occurrence,
+    },
+}
+
+/// Structural use. A regex marker in the target file
+///
+/// If the regex contains a group named `marker`, the content is inserted relative to this group
+/// instead of the whole match. Capture groups can be used in the template content with the
+/// [CaptureHelper] (`{{capture 1}}`, `{{capture "name"}}`), the content is never expanded
+/// otherwise (so a `$` stays as it is).
+///
+/// # Example
+/// ```yaml
+/// action:
+///     Append:
+///         AfterRegex:
+///             pattern: "^use .*;$"
+///             multiline: true
+///             occurrence: last
+/// ---
+/// action:
+///     Append:
+///         BeforeRegex:
+///             pattern: "(?s)impl (?P<type>Foo) \\{.*?\\n(?P<marker>\\})"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RegexMarker {
+    pub pattern: String,
+
+    /// Makes `^` and `$` match at the begin and end of every line, default=false
+    #[serde(default)]
+    pub multiline: bool,
+
+    /// default=first
+    #[serde(default)]
+    pub occurrence: Occurrence,
+}
+
+/// Structural use. A literal text or regex to search for in the target file
+///
+/// If the regex contains a group named `marker`, only this group is replaced. Capture groups can be
+/// used in the content with the [CaptureHelper] like in [RegexMarker]
+///
+/// # Example
+/// ```yaml
+/// action:
+///     Replace:
+///         Text:
+///             marker: "OldName"
+///             occurrence: all
+/// ---
+/// action:
+///     Replace:
+///         Regex:
+///             pattern: "version = \"(\\d+)\\.\\d+\""
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Text(Marker),
+    Regex(RegexMarker),
+}
+
+/// Structural use. What a Delete action removes
+///
+/// # Example
+/// ```yaml
+/// action:
+///     Delete:
+///         Lines:
+///             Text: "mod {{snake_case name}};"
+/// ---
+/// action:
+///     Delete:
+///         Block:
+///             begin: "// begin {{name}}"
+///             end: "// end {{name}}"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum DeleteTarget {
+    /// Removes every line which contains a selected match
+    Lines(Pattern),
+
+    /// Removes the lines from the begin marker up to the next end marker
+    Block {
+        begin: Marker,
+        end: String,
+
+        /// Only removes the lines between the markers, default=false
+        #[serde(default)]
+        keep_markers: bool,
+    },
+}
+
+/// Structural use. A region owned by a template
+///
+/// The markers have to be in separate lines and regions cannot be nested. With `if_missing: append_end` a missing region is created (including the markers) at the end
+/// of the file.
+///
+/// # Example
+/// ```yaml
+/// action:
+///     Region:
+///         begin: "// rustgen:begin routes"
+///         end: "// rustgen:end routes"
+/// if_missing: append_end
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Region {
+    /// The first occurrence is used by default, see [Marker]
+    pub begin: Marker,
+    pub end: String,
+}
+
+/// Structural use. How the lines of an appended block are indented
+///
+/// The common indentation of the block is replaced. The indentation inside of the block is
+/// converted to tabs or spaces like the new indentation, a number of spaces becomes tabs in a file
+/// indented with tabs.
+///
+/// # Example
+/// ```yaml
+/// indent: marker  # like the line of the marker
+/// ---
+/// indent: detect  # like the next line after (or the previous line before) the marker
+/// ---
+/// indent: 4       # four spaces (or a tab)
+/// ---
+/// indent: "\t\t"  # the literal text
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Indent {
+    Spaces(usize),
+    Mode(IndentMode),
+    Text(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IndentMode {
+    /// The indentation of the line containing the marker
+    Marker,
+
+    /// The indentation of the first non-blank line after (or before) the marker
+    Detect,
+}
+
+/// Structural use. When an Append action is skipped, makes generators idempotent
+///
+/// # Example
+/// ```yaml
+/// skip_if: "import {{camel_case name}} from"
+/// ---
+/// skip_if:
+///     regex: "^mod {{snake_case name}};$"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum SkipCondition {
+    /// The target file contains the text
+    Contains(String),
+
+    /// The target file matches the regex (`^` and `$` match at every line)
+    Matches { regex: String },
+}
+
+/// Structural use. Which occurrences of a marker are used if it is in the file multiple times
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Occurrence {
+    #[default]
+    First,
+    Last,
+    All,
+
+    /// The n-th occurrence, starting at 1
+    Nth(usize),
 }
 
 /// A helper which replaces with regex
@@ -130,3 +694,16 @@ pub struct ConcatHelper;
 /// The available syntax is described [here](https://docs.rs/chrono/0.4.19/chrono/format/strftime/index.html)
 ///
 pub struct TimeHelper;
+
+/// A helper for using a capture group of the regex of an `AfterRegex`, `BeforeRegex` or `Replace`
+/// action in the content. The group is selected by its index or name, the text of the group is
+/// filled in for every match when the content is written (empty for other actions)
+///
+/// # Example
+///
+/// ```hbs
+/// impl {{capture "type"}} {
+///     // The first group: {{capture 1}}
+/// }
+/// ```
+pub struct CaptureHelper;
@@ -0,0 +1,779 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use proc_macro2::Span;
+use quote::ToTokens;
+use syn::parse::Parse;
+use syn::spanned::Spanned;
+use syn::visit::Visit;
+use syn::{
+    Arm, Expr, ExprMatch, File, ImplItem, Item, ItemEnum, ItemImpl, ItemMod, ItemUse, Pat,
+    Signature, Type, UseTree, Variant,
+};
+
+use crate::rustgen_error::{ErrorKind, RustgenError, RustgenResult};
+use crate::template::writer::{
+    indent_width, insert_lines, leading_whitespace, line_end, line_start, reindent, splice,
+};
+use crate::template::{Indent, RustEdit};
+
+/// The result of a [RustEdit]
+pub enum RustEditOutcome {
+    /// The new content of the file
+    Edited(String),
+
+    /// The file already contains the item
+    Present,
+
+    /// The item which should be edited is not in the file (e.g. `enum Command`)
+    Missing(String),
+}
+
+impl RustEdit {
+    /// Applies the edit with the rendered template to the content of a rust file
+    pub fn apply(
+        &self,
+        path: &str,
+        content: &str,
+        block: &str,
+        indent: Option<&Indent>,
+    ) -> RustgenResult<RustEditOutcome> {
+        let file = syn::parse_file(content).map_err(|error| {
+            syntax_error(
+                ErrorKind::MissingMarker,
+                format!("Could not parse {}", path),
+                error,
+            )
+        })?;
+        let editor = RustEditor {
+            content,
+            block: block.trim(),
+            indent,
+        };
+
+        match self {
+            RustEdit::AddMod => editor.add_mod(&file),
+            RustEdit::AddUse => editor.add_use(&file),
+            RustEdit::AddVariant(name) => editor.add_variant(&file, name),
+            RustEdit::AddMatchArm(name) => editor.add_match_arm(&file, name),
+            RustEdit::AddMethod(name) => editor.add_method(&file, name),
+        }
+    }
+}
+
+struct RustEditor<'a> {
+    content: &'a str,
+    block: &'a str,
+    indent: Option<&'a Indent>,
+}
+
+impl RustEditor<'_> {
+    fn add_mod(&self, file: &File) -> RustgenResult<RustEditOutcome> {
+        let new: ItemMod = parse_block(self.block, "mod declaration (e.g. `pub mod name;`)")?;
+
+        if new.content.is_some() {
+            return Err(RustgenError::new(
+                ErrorKind::Render,
+                "The rendered template has to be a mod declaration without a body (e.g. `pub mod \
+                name;`)",
+            ));
+        }
+
+        let mut declarations = file.items.iter().filter_map(|item| match item {
+            Item::Mod(declaration) if declaration.content.is_none() => Some(declaration),
+            _ => None,
+        });
+
+        if declarations
+            .clone()
+            .any(|declaration| declaration.ident == new.ident)
+        {
+            return Ok(RustEditOutcome::Present);
+        }
+
+        let mut uses = file
+            .items
+            .iter()
+            .filter(|item| matches!(item, Item::Use(_)));
+        let position = match declarations.next_back() {
+            Some(declaration) => line_end(self.content, range(declaration.span()).end),
+            None => self.top_position(file, uses.next_back()),
+        };
+
+        Ok(self.insert_lines(position))
+    }
+
+    fn add_use(&self, file: &File) -> RustgenResult<RustEditOutcome> {
+        let new: ItemUse = parse_block(self.block, "use declaration (e.g. `use crate::name;`)")?;
+        let mut new_tree = UseNode::default();
+        new_tree.add(&new.tree);
+
+        let uses: Vec<&ItemUse> = file
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Use(declaration) => Some(declaration),
+                _ => None,
+            })
+            .collect();
+
+        let mut existing_paths = BTreeSet::new();
+
+        for declaration in &uses {
+            let mut tree = UseNode::default();
+            tree.add(&declaration.tree);
+            tree.paths("", &mut existing_paths);
+        }
+
+        let mut new_paths = BTreeSet::new();
+        new_tree.paths("", &mut new_paths);
+
+        if new_paths.is_subset(&existing_paths) {
+            return Ok(RustEditOutcome::Present);
+        }
+
+        let new_visibility = self.text(new.vis.span(), &new.vis);
+        let target = uses.iter().find(|declaration| {
+            let mut tree = UseNode::default();
+            tree.add(&declaration.tree);
+
+            declaration.attrs.is_empty()
+                && self.is_single_line(declaration.span())
+                && declaration.leading_colon.is_some() == new.leading_colon.is_some()
+                && self.text(declaration.vis.span(), &declaration.vis) == new_visibility
+                && new_tree
+                    .children
+                    .iter()
+                    .all(|(name, _)| tree.children.iter().any(|(root, _)| root == name))
+        });
+
+        let target = match target {
+            Some(target) => target,
+            None => {
+                let position = match uses.last() {
+                    Some(last) => line_end(self.content, range(last.span()).end),
+                    None => self.top_position(file, None),
+                };
+
+                return Ok(self.insert_lines(position));
+            }
+        };
+
+        let mut tree = UseNode::default();
+        tree.add(&target.tree);
+        tree.merge(new_tree);
+
+        let visibility = match new_visibility.is_empty() {
+            true => String::new(),
+            false => format!("{} ", new_visibility),
+        };
+        let declaration = format!(
+            "{}use {}{};",
+            visibility,
+            match new.leading_colon {
+                Some(_) => "::",
+                None => "",
+            },
+            join_use_entries(tree.entries())
+        );
+        let edits = vec![(range(target.span()), declaration)];
+
+        Ok(RustEditOutcome::Edited(
+            splice(self.content, edits).unwrap_or_default(),
+        ))
+    }
+
+    /// Merged declarations are rewritten on one line, so only declarations without line breaks or
+    /// comments are merged to keep the formatting of the file
+    fn is_single_line(&self, span: Span) -> bool {
+        let text = &self.content[range(span)];
+
+        !text.contains('\n') && !text.contains("//") && !text.contains("/*")
+    }
+
+    fn add_variant(&self, file: &File, name: &str) -> RustgenResult<RustEditOutcome> {
+        let new: Variant = parse_block(self.block.trim_end_matches(','), "enum variant")?;
+        let found = find_item(&file.items, &|item| match item {
+            Item::Enum(item) if item.ident == name => Some(item),
+            _ => None,
+        });
+        let item: &ItemEnum = match found {
+            Some(item) => item,
+            None => return Ok(RustEditOutcome::Missing(format!("enum {}", name))),
+        };
+
+        if item
+            .variants
+            .iter()
+            .any(|variant| variant.ident == new.ident)
+        {
+            return Ok(RustEditOutcome::Present);
+        }
+
+        let mut edits = vec![];
+        let last = item.variants.last().map(|variant| range(variant.span()));
+
+        if let Some(last) = &last {
+            if !item.variants.trailing_punct() {
+                edits.push((last.end..last.end, String::from(",")));
+            }
+        }
+
+        let variant = format!("{},", self.block.trim_end_matches(','));
+        edits.push(self.insert_before_close(item.brace_token.span.close(), &variant, last));
+
+        Ok(RustEditOutcome::Edited(
+            splice(self.content, edits).unwrap_or_default(),
+        ))
+    }
+
+    fn add_match_arm(&self, file: &File, name: &str) -> RustgenResult<RustEditOutcome> {
+        let new: Arm = parse_block(self.block, "match arm")?;
+        let mut finder = MatchFinder {
+            function: name,
+            in_function: false,
+            found: None,
+        };
+
+        finder.visit_file(file);
+
+        let expression = match finder.found {
+            Some(expression) => expression,
+            None => return Ok(RustEditOutcome::Missing(format!("match in fn {}", name))),
+        };
+
+        let pattern = arm_pattern(&new);
+
+        if expression
+            .arms
+            .iter()
+            .any(|arm| arm_pattern(arm) == pattern)
+        {
+            return Ok(RustEditOutcome::Present);
+        }
+
+        let mut arm = self.block.to_string();
+
+        if new.comma.is_none() && !matches!(*new.body, Expr::Block(_)) {
+            arm.push(',');
+        }
+
+        let mut edits = vec![];
+        let last = expression.arms.last();
+
+        match last {
+            // A trailing `_` arm stays the last arm
+            Some(last) if matches!(last.pat, Pat::Wild(_)) && last.guard.is_none() => {
+                let start = range(last.span()).start;
+
+                match self.starts_line(start) {
+                    true => {
+                        let position = line_start(self.content, start);
+                        let indentation = self.indentation(leading_whitespace(
+                            &self.content[position..line_end(self.content, position)],
+                        ));
+
+                        edits.push((
+                            position..position,
+                            format!("{}\n", reindent(&arm, &indentation)),
+                        ));
+                    }
+                    false => edits.push((start..start, format!("{} ", arm))),
+                }
+            }
+            _ => {
+                if let Some(last) = last {
+                    if last.comma.is_none() && !matches!(*last.body, Expr::Block(_)) {
+                        let end = range(last.span()).end;
+
+                        edits.push((end..end, String::from(",")));
+                    }
+                }
+
+                edits.push(self.insert_before_close(
+                    expression.brace_token.span.close(),
+                    &arm,
+                    last.map(|last| range(last.span())),
+                ));
+            }
+        }
+
+        Ok(RustEditOutcome::Edited(
+            splice(self.content, edits).unwrap_or_default(),
+        ))
+    }
+
+    fn add_method(&self, file: &File, name: &str) -> RustgenResult<RustEditOutcome> {
+        let new: ImplItem = parse_block(self.block, "impl item (e.g. a method)")?;
+        let is_impl_of = |item: &ItemImpl| match &*item.self_ty {
+            Type::Path(path) => path
+                .path
+                .segments
+                .last()
+                .is_some_and(|segment| segment.ident == name),
+            _ => false,
+        };
+
+        // Inherent impl blocks are preferred over trait implementations
+        let found = find_item(&file.items, &|item| match item {
+            Item::Impl(item) if item.trait_.is_none() && is_impl_of(item) => Some(item),
+            _ => None,
+        })
+        .or_else(|| {
+            find_item(&file.items, &|item| match item {
+                Item::Impl(item) if is_impl_of(item) => Some(item),
+                _ => None,
+            })
+        });
+
+        let item: &ItemImpl = match found {
+            Some(item) => item,
+            None => return Ok(RustEditOutcome::Missing(format!("impl {}", name))),
+        };
+
+        if let Some(ident) = impl_item_name(&new) {
+            if item
+                .items
+                .iter()
+                .any(|existing| impl_item_name(existing) == Some(ident.clone()))
+            {
+                return Ok(RustEditOutcome::Present);
+            }
+        }
+
+        let last = item.items.last().map(|existing| range(existing.span()));
+        let method = match last {
+            // Separates the method from the existing items by a blank line
+            Some(_) => format!("\n{}", self.block),
+            None => self.block.to_string(),
+        };
+
+        let edits = vec![self.insert_before_close(item.brace_token.span.close(), &method, last)];
+
+        Ok(RustEditOutcome::Edited(
+            splice(self.content, edits).unwrap_or_default(),
+        ))
+    }
+
+    /// The line after the inner attributes and the given item, or before the first item
+    fn top_position(&self, file: &File, after: Option<&Item>) -> usize {
+        if let Some(item) = after {
+            return line_end(self.content, range(item.span()).end);
+        }
+
+        match (file.items.first(), file.attrs.last()) {
+            (Some(item), _) => line_start(self.content, range(item.span()).start),
+            (None, Some(attribute)) => line_end(self.content, range(attribute.span()).end),
+            (None, None) => 0,
+        }
+    }
+
+    /// Inserts the block as own lines at the line start
+    fn insert_lines(&self, position: usize) -> RustEditOutcome {
+        RustEditOutcome::Edited(
+            insert_lines(self.content, vec![position], self.block, self.indent).unwrap_or_default(),
+        )
+    }
+
+    /// Inserts the text as own lines before the closing brace, indented like the last item in
+    /// the braces (or one level deeper than the brace)
+    fn insert_before_close(
+        &self,
+        close: Span,
+        text: &str,
+        last: Option<Range<usize>>,
+    ) -> (Range<usize>, String) {
+        let close = range(close).start;
+        let brace_line = line_start(self.content, close);
+        let brace_indentation = leading_whitespace(&self.content[brace_line..close]);
+
+        let detected = match last {
+            Some(last) if self.starts_line(last.start) => {
+                let line = line_start(self.content, last.start);
+
+                leading_whitespace(&self.content[line..last.start]).to_string()
+            }
+            _ => match brace_indentation.contains('\t') {
+                true => format!("{}\t", brace_indentation),
+                false => format!("{}    ", brace_indentation),
+            },
+        };
+        let text = reindent(text, &self.indentation(&detected));
+
+        match self.content[brace_line..close].trim().is_empty() {
+            true => (brace_line..brace_line, format!("{}\n", text)),
+            false => {
+                let end = brace_line + self.content[brace_line..close].trim_end().len();
+
+                (end..close, format!("\n{}\n{}", text, brace_indentation))
+            }
+        }
+    }
+
+    /// The indentation of the header (if it is a fixed one) or the detected one
+    fn indentation(&self, detected: &str) -> String {
+        match self.indent {
+            Some(Indent::Spaces(width)) => indent_width(*width, self.content),
+            Some(Indent::Text(text)) => text.clone(),
+            _ => detected.to_string(),
+        }
+    }
+
+    /// Whether only whitespace is in front of the position in its line
+    fn starts_line(&self, position: usize) -> bool {
+        self.content[line_start(self.content, position)..position]
+            .trim()
+            .is_empty()
+    }
+
+    /// The original text of a syntax node (empty if it has no tokens, e.g. a private visibility)
+    fn text(&self, span: Span, node: &impl ToTokens) -> String {
+        if node.to_token_stream().is_empty() {
+            return String::new();
+        }
+
+        self.content[range(span)].to_string()
+    }
+}
+
+/// Finds the first `match` in the function (or method) with the name
+struct MatchFinder<'a, 'ast> {
+    function: &'a str,
+    in_function: bool,
+    found: Option<&'ast ExprMatch>,
+}
+
+impl<'ast> Visit<'ast> for MatchFinder<'_, 'ast> {
+    fn visit_item_fn(&mut self, item: &'ast syn::ItemFn) {
+        self.visit_function(&item.sig, |finder| finder.visit_block(&item.block));
+    }
+
+    fn visit_impl_item_fn(&mut self, item: &'ast syn::ImplItemFn) {
+        self.visit_function(&item.sig, |finder| finder.visit_block(&item.block));
+    }
+
+    // A closure has its own body, like a nested fn
+    fn visit_expr_closure(&mut self, closure: &'ast syn::ExprClosure) {
+        let outer = self.in_function;
+
+        self.in_function = false;
+        syn::visit::visit_expr_closure(self, closure);
+        self.in_function = outer;
+    }
+
+    fn visit_expr_match(&mut self, expression: &'ast ExprMatch) {
+        if self.in_function && self.found.is_none() {
+            self.found = Some(expression);
+        }
+    }
+}
+
+impl<'ast> MatchFinder<'_, 'ast> {
+    /// Only the own body of the function is searched, not the ones of nested functions
+    fn visit_function(&mut self, signature: &'ast Signature, body: impl FnOnce(&mut Self)) {
+        let outer = self.in_function;
+
+        self.in_function = signature.ident == self.function;
+        body(self);
+        self.in_function = outer;
+    }
+}
+
+/// A use tree, merging multiple declarations
+#[derive(Debug, Clone, Default, PartialEq)]
+struct UseNode {
+    children: Vec<(String, UseNode)>,
+
+    /// The path itself is imported (`self`)
+    imported: bool,
+
+    /// The path is imported as these names
+    renames: Vec<String>,
+
+    /// Everything in the path is imported (`*`)
+    glob: bool,
+}
+
+impl UseNode {
+    fn add(&mut self, tree: &UseTree) {
+        match tree {
+            UseTree::Path(path) => self.child(path.ident.to_string()).add(&path.tree),
+            UseTree::Name(name) if name.ident == "self" => self.imported = true,
+            UseTree::Name(name) => self.child(name.ident.to_string()).imported = true,
+            UseTree::Rename(rename) => {
+                let node = match rename.ident == "self" {
+                    true => self,
+                    false => self.child(rename.ident.to_string()),
+                };
+                let name = rename.rename.to_string();
+
+                if !node.renames.contains(&name) {
+                    node.renames.push(name);
+                }
+            }
+            UseTree::Glob(_) => self.glob = true,
+            UseTree::Group(group) => {
+                for tree in &group.items {
+                    self.add(tree);
+                }
+            }
+        }
+    }
+
+    fn merge(&mut self, other: UseNode) {
+        self.imported |= other.imported;
+        self.glob |= other.glob;
+
+        for name in other.renames {
+            if !self.renames.contains(&name) {
+                self.renames.push(name);
+            }
+        }
+
+        for (name, child) in other.children {
+            self.child(name).merge(child);
+        }
+    }
+
+    fn child(&mut self, name: String) -> &mut UseNode {
+        let index = match self.children.iter().position(|(child, _)| *child == name) {
+            Some(index) => index,
+            None => {
+                self.children.push((name, UseNode::default()));
+                self.children.len() - 1
+            }
+        };
+
+        &mut self.children[index].1
+    }
+
+    /// Collects every imported path (e.g. `crate::a::B`, `crate::c::*`)
+    fn paths(&self, prefix: &str, paths: &mut BTreeSet<String>) {
+        if self.imported {
+            paths.insert(prefix.to_string());
+        }
+
+        for name in &self.renames {
+            paths.insert(format!("{} as {}", prefix, name));
+        }
+
+        if self.glob {
+            paths.insert(format!("{}::*", prefix));
+        }
+
+        for (name, child) in &self.children {
+            match prefix.is_empty() {
+                true => child.paths(name, paths),
+                false => child.paths(&format!("{}::{}", prefix, name), paths),
+            }
+        }
+    }
+
+    /// The entries of the use group of this node (e.g. `self`, `a::{B, C}`, `*`)
+    fn entries(&self) -> Vec<String> {
+        let mut entries = vec![];
+
+        if self.imported {
+            entries.push(String::from("self"));
+        }
+
+        for name in &self.renames {
+            entries.push(format!("self as {}", name));
+        }
+
+        if self.glob {
+            entries.push(String::from("*"));
+        }
+
+        for (name, child) in &self.children {
+            let child_entries = child.entries();
+
+            entries.push(match child_entries.as_slice() {
+                [entry] if entry == "self" => name.clone(),
+                [entry] if entry.starts_with("self as ") => {
+                    format!("{}{}", name, entry.trim_start_matches("self"))
+                }
+                _ => format!("{}::{}", name, join_use_entries(child_entries)),
+            });
+        }
+
+        entries
+    }
+}
+
+fn join_use_entries(entries: Vec<String>) -> String {
+    match entries.len() {
+        1 => entries.join(""),
+        _ => format!("{{{}}}", entries.join(", ")),
+    }
+}
+
+/// Finds an item in the items and all inline modules
+fn find_item<'a, T>(
+    items: &'a [Item],
+    matcher: &impl Fn(&'a Item) -> Option<&'a T>,
+) -> Option<&'a T> {
+    items.iter().find_map(|item| match item {
+        Item::Mod(ItemMod {
+            content: Some((_, items)),
+            ..
+        }) => matcher(item).or_else(|| find_item(items, matcher)),
+        _ => matcher(item),
+    })
+}
+
+fn arm_pattern(arm: &Arm) -> String {
+    let guard = arm
+        .guard
+        .as_ref()
+        .map(|(_, guard)| guard.to_token_stream().to_string());
+
+    format!("{} {:?}", arm.pat.to_token_stream(), guard)
+}
+
+fn impl_item_name(item: &ImplItem) -> Option<String> {
+    match item {
+        ImplItem::Fn(item) => Some(item.sig.ident.to_string()),
+        ImplItem::Const(item) => Some(item.ident.to_string()),
+        ImplItem::Type(item) => Some(item.ident.to_string()),
+        _ => None,
+    }
+}
+
+fn range(span: Span) -> Range<usize> {
+    span.byte_range()
+}
+
+fn parse_block<T: Parse>(block: &str, expected: &str) -> RustgenResult<T> {
+    syn::parse_str(block).map_err(|error| {
+        syntax_error(
+            ErrorKind::Render,
+            format!("The rendered template is no valid {}", expected),
+            error,
+        )
+    })
+}
+
+fn syntax_error(kind: ErrorKind, message: String, error: syn::Error) -> RustgenError {
+    let start = error.span().start();
+
+    RustgenError::new(
+        kind,
+        format!(
+            "{}: {} (line {}, column {})",
+            message,
+            error,
+            start.line,
+            start.column + 1
+        ),
+    )
+    .with_source(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(edit: RustEdit, content: &str, block: &str) -> String {
+        match edit.apply("lib.rs", content, block, None).unwrap() {
+            RustEditOutcome::Edited(content) => content,
+            RustEditOutcome::Present => String::from("present"),
+            RustEditOutcome::Missing(item) => format!("missing {}", item),
+        }
+    }
+
+    #[test]
+    fn test_add_use_merges_tree() {
+        let content = "use std::fmt;\nuse crate::a::{B, c::D};\n\nfn main() {}\n";
+
+        assert_eq!(
+            apply(RustEdit::AddUse, content, "use crate::a::c::{E, D};"),
+            "use std::fmt;\nuse crate::a::{B, c::{D, E}};\n\nfn main() {}\n"
+        );
+        assert_eq!(
+            apply(RustEdit::AddUse, content, "use crate::a::B;"),
+            "present"
+        );
+    }
+
+    #[test]
+    fn test_add_use_keeps_multi_line_group() {
+        let content = "use crate::a::{\n    B, // the b\n    C,\n};\n\nfn main() {}\n";
+
+        assert_eq!(
+            apply(RustEdit::AddUse, content, "use crate::a::D;"),
+            "use crate::a::{\n    B, // the b\n    C,\n};\nuse crate::a::D;\n\nfn main() {}\n"
+        );
+    }
+
+    #[test]
+    fn test_add_match_arm() {
+        let content = "fn a(x: u8) -> u8 {\n    match x {\n        1 => 2\n    }\n}\n";
+
+        assert_eq!(
+            apply(RustEdit::AddMatchArm(String::from("a")), content, "2 => 3"),
+            "fn a(x: u8) -> u8 {\n    match x {\n        1 => 2,\n        2 => 3,\n    }\n}\n"
+        );
+        assert_eq!(
+            apply(RustEdit::AddMatchArm(String::from("b")), content, "2 => 3"),
+            "missing match in fn b"
+        );
+    }
+
+    #[test]
+    fn test_add_match_arm_before_inline_wildcard() {
+        assert_eq!(
+            apply(
+                RustEdit::AddMatchArm(String::from("a")),
+                "fn a(x: u8) -> u8 {\n    match x { 1 => 2, _ => 0 }\n}\n",
+                "2 => 3"
+            ),
+            "fn a(x: u8) -> u8 {\n    match x { 1 => 2, 2 => 3, _ => 0 }\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_add_match_arm_skips_nested_functions() {
+        let content =
+            "fn a(x: u8) -> u8 {\n    fn b(y: u8) -> u8 {\n        match y {\n            \
+            1 => 1,\n        }\n    }\n    let c = |z: u8| match z {\n        1 => 1,\n    };\n    \
+            match x {\n        1 => 2,\n    }\n}\n";
+
+        assert_eq!(
+            apply(RustEdit::AddMatchArm(String::from("a")), content, "2 => 3"),
+            content.replace("        1 => 2,\n", "        1 => 2,\n        2 => 3,\n")
+        );
+    }
+
+    #[test]
+    fn test_add_variant_to_inline_enum() {
+        assert_eq!(
+            apply(
+                RustEdit::AddVariant(String::from("A")),
+                "mod m {\n    enum A { B }\n}\n",
+                "C"
+            ),
+            "mod m {\n    enum A { B,\n        C,\n    }\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_content() {
+        let error = RustEdit::AddMod
+            .apply("lib.rs", "", "pub mod ;", None)
+            .err()
+            .unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::Render);
+    }
+
+    #[test]
+    fn test_invalid_target() {
+        let error = RustEdit::AddMod
+            .apply("lib.rs", "fn main( {}", "pub mod a;", None)
+            .err()
+            .unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::MissingMarker);
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::BTreeSet;
+
+use handlebars::template::{HelperTemplate, Parameter, TemplateElement};
+use handlebars::{Handlebars, JsonValue, Template};
+
+/// Block helpers which change the context, variables inside of them are not top level variables
+const CONTEXT_HELPERS: [&str; 2] = ["each", "with"];
+
+/// Collects the names of all top level variables a template reads
+///
+/// Helpers (registered in the given instance) and variables which are defined by the template
+/// itself (`{{set "name" ...}}`) are not included.
+pub fn collect_variables(template: &Template, bars: &Handlebars) -> BTreeSet<String> {
+    let mut collector = VariableCollector {
+        bars,
+        used: BTreeSet::new(),
+        defined: BTreeSet::new(),
+    };
+
+    collector.visit_template(template);
+
+    collector
+        .used
+        .difference(&collector.defined)
+        .cloned()
+        .collect()
+}
+
+struct VariableCollector<'a, 'reg> {
+    bars: &'a Handlebars<'reg>,
+    used: BTreeSet<String>,
+    defined: BTreeSet<String>,
+}
+
+impl VariableCollector<'_, '_> {
+    fn visit_template(&mut self, template: &Template) {
+        for element in &template.elements {
+            self.visit_element(element);
+        }
+    }
+
+    fn visit_element(&mut self, element: &TemplateElement) {
+        match element {
+            TemplateElement::HTMLExpression(parameter) => self.visit_parameter(parameter),
+            TemplateElement::Expression(helper) | TemplateElement::HelperBlock(helper) => {
+                self.visit_helper(helper)
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_helper(&mut self, helper: &HelperTemplate) {
+        let name = helper.name.as_name().unwrap_or_default();
+        let is_helper = self.bars.get_helper(name).is_some();
+
+        if !is_helper {
+            self.visit_parameter(&helper.name);
+        }
+
+        if name == "set" {
+            if let Some(Parameter::Literal(JsonValue::String(variable))) = helper.params.first() {
+                self.defined.insert(variable.clone());
+            }
+        }
+
+        for parameter in helper.params.iter().chain(helper.hash.values()) {
+            self.visit_parameter(parameter);
+        }
+
+        if CONTEXT_HELPERS.contains(&name) {
+            return;
+        }
+
+        for template in helper.template.iter().chain(helper.inverse.iter()) {
+            self.visit_template(template);
+        }
+    }
+
+    fn visit_parameter(&mut self, parameter: &Parameter) {
+        match parameter {
+            Parameter::Path(_) => {
+                let path = parameter.as_name().unwrap_or_default();
+                let root = path.split(['.', '/', '[']).next().unwrap_or_default();
+
+                if !root.is_empty() && !root.starts_with('@') && root != "this" && root != ".." {
+                    self.used.insert(root.to_string());
+                }
+            }
+            Parameter::Subexpression(subexpression) => self.visit_element(&subexpression.element),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::helpers::add_helpers;
+
+    fn variables(template: &str) -> Vec<String> {
+        let mut bars = Handlebars::new();
+
+        add_helpers(&mut bars);
+
+        collect_variables(&Template::compile(template).unwrap(), &bars)
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn test_helper_arguments() {
+        assert_eq!(
+            variables("{{snake_case name}} {{concat prefix (upper_case suffix)}} {{plain}}"),
+            ["name", "plain", "prefix", "suffix"]
+        );
+    }
+
+    #[test]
+    fn test_context_helpers() {
+        assert_eq!(
+            variables(
+                "{{#each fields}}{{name}}{{this}}{{@index}}{{/each}}{{#with user}}{{id}}{{/with}}"
+            ),
+            ["fields", "user"]
+        );
+        assert_eq!(
+            variables("{{#if flag}}{{yes}}{{else}}{{no}}{{/if}}"),
+            ["flag", "no", "yes"]
+        );
+    }
+
+    #[test]
+    fn test_set_variables() {
+        assert_eq!(
+            variables("{{set \"module\" (snake_case name)}}{{module}} {{config.path}}"),
+            ["config", "name"]
+        );
+    }
+}
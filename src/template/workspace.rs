@@ -0,0 +1,801 @@
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+
+use similar::TextDiff;
+
+use crate::rustgen_error::{ErrorKind, RustgenError, RustgenResult};
+use crate::template::{StagedAsset, StagedFile, StagedLink, TextFormat, Workspace};
+
+const COLOR_RED: &str = "\x1b[31m";
+const COLOR_GREEN: &str = "\x1b[32m";
+const COLOR_CYAN: &str = "\x1b[36m";
+const COLOR_BOLD: &str = "\x1b[1m";
+const COLOR_RESET: &str = "\x1b[0m";
+
+/// The UTF-8 byte order mark
+const BOM: char = '\u{feff}';
+
+impl Workspace {
+    /// Creates a workspace for files relative to `root`
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            files: Default::default(),
+            assets: Default::default(),
+            links: Default::default(),
+        }
+    }
+
+    /// Returns the current content of the file or None if it does not exist
+    pub fn read(&self, path: &str) -> RustgenResult<Option<String>> {
+        let path = self.root.join(path);
+
+        if let Some(file) = self.files.get(&path) {
+            return Ok(Some(file.content.clone()));
+        }
+
+        Ok(
+            read_existing(&path)?
+                .map(|original| TextFormat::detect(&original).normalize(&original)),
+        )
+    }
+
+    /// Returns the current content of the file and fails if it does not exist
+    pub fn read_existing(&self, path: &str) -> RustgenResult<String> {
+        self.read(path)?.ok_or_else(|| {
+            RustgenError::new(
+                ErrorKind::Io,
+                format!("Could not open file for append {}", path),
+            )
+        })
+    }
+
+    pub fn exists(&self, path: &str) -> RustgenResult<bool> {
+        Ok(self.read(path)?.is_some())
+    }
+
+    /// Stages the content of a file, it is written to the disk on [Workspace::commit]
+    pub fn write(&mut self, path: &str, content: String) -> RustgenResult<()> {
+        let path = self.root.join(path);
+
+        match self.files.get_mut(&path) {
+            Some(file) => file.content = file.format.normalize(&content),
+            None => {
+                let original = read_existing(&path)?;
+                let format = original
+                    .as_deref()
+                    .map(TextFormat::detect)
+                    .unwrap_or_default();
+                let content = format.normalize(&content);
+                let original_mode = read_mode(&path);
+
+                self.files.insert(
+                    path,
+                    StagedFile {
+                        original,
+                        content,
+                        format,
+                        mode: None,
+                        original_mode,
+                    },
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the current bytes of the file (text or binary) or None if it does not exist
+    pub fn read_bytes(&self, path: &str) -> RustgenResult<Option<Vec<u8>>> {
+        let path = self.root.join(path);
+
+        if let Some(asset) = self.assets.get(&path) {
+            return Ok(Some(asset.content.clone()));
+        }
+
+        if let Some(file) = self.files.get(&path) {
+            return Ok(Some(file.output().into_bytes()));
+        }
+
+        match fs::read(&path) {
+            Ok(content) => Ok(Some(content)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Stages a binary file, its content is written as is on [Workspace::commit]
+    pub fn write_bytes(&mut self, path: &str, content: Vec<u8>) -> RustgenResult<()> {
+        let original = match self.assets.remove(&self.root.join(path)) {
+            Some(asset) => asset.original,
+            None => self.read_bytes(path)?,
+        };
+
+        self.assets
+            .insert(self.root.join(path), StagedAsset { original, content });
+
+        Ok(())
+    }
+
+    /// Stages the permissions of a file (octal like `0o755`), the file has to be staged or exist
+    pub fn set_mode(&mut self, path: &str, mode: u32) -> RustgenResult<()> {
+        if !self.files.contains_key(&self.root.join(path)) {
+            let content = self.read_existing(path)?;
+
+            self.write(path, content)?;
+        }
+
+        if let Some(file) = self.files.get_mut(&self.root.join(path)) {
+            file.mode = Some(mode);
+        }
+
+        Ok(())
+    }
+
+    /// Returns where the symlink at the path points to, None if there is no symlink
+    pub fn read_link(&self, path: &str) -> RustgenResult<Option<PathBuf>> {
+        let path = self.root.join(path);
+
+        if let Some(link) = self.links.get(&path) {
+            return Ok(Some(link.target.clone()));
+        }
+
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => Ok(Some(fs::read_link(&path)?)),
+            Ok(_) => Ok(None),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Stages a symlink at the path pointing to the target (relative to the directory of the
+    /// link). Both have to be inside of the root, an existing symlink is replaced
+    pub fn link(&mut self, path: &str, target: &str) -> RustgenResult<()> {
+        let link = normalize(&self.root.join(path));
+        let directory = link.parent().unwrap_or(&self.root).to_path_buf();
+
+        for checked in [&link, &normalize(&directory.join(target))] {
+            if checked == &self.root || !checked.starts_with(&self.root) {
+                return Err(RustgenError::new(
+                    ErrorKind::Config,
+                    format!(
+                        "Cannot link {} to {}, both have to be inside of the project {}",
+                        path,
+                        target,
+                        self.root.display()
+                    ),
+                ));
+            }
+        }
+
+        let original = match self.links.remove(&link) {
+            Some(staged) => staged.original,
+            None => self.read_link(path)?,
+        };
+
+        self.links.insert(
+            link,
+            StagedLink {
+                target: PathBuf::from(target),
+                original,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Writes all staged text and binary files to the disk and creates the symlinks. If one of them fails, all
+    /// already written files and links are rolled back (original contents are restored, created
+    /// files and directories are deleted)
+    pub fn commit(&self) -> RustgenResult<()> {
+        let mut written: Vec<(&PathBuf, &StagedFile)> = vec![];
+        let mut copied: Vec<(&PathBuf, &StagedAsset)> = vec![];
+        let mut linked: Vec<(&PathBuf, &StagedLink)> = vec![];
+        let mut created_directories = vec![];
+
+        for (path, file) in self.changed_files() {
+            if let Err(error) = write_staged(path, file, &mut created_directories) {
+                rollback(&written, &copied, &linked, &created_directories);
+
+                return Err(self.commit_failed(path, error));
+            }
+
+            written.push((path, file));
+        }
+
+        for (path, asset) in self.changed_assets() {
+            if let Err(error) = write_file(path, &asset.content, &mut created_directories) {
+                rollback(&written, &copied, &linked, &created_directories);
+
+                return Err(self.commit_failed(path, error));
+            }
+
+            copied.push((path, asset));
+        }
+
+        for (path, link) in self.changed_links() {
+            if let Err(error) = create_link(path, link, &mut created_directories) {
+                rollback(&written, &copied, &linked, &created_directories);
+
+                return Err(self.commit_failed(path, error));
+            }
+
+            linked.push((path, link));
+        }
+
+        Ok(())
+    }
+
+    fn commit_failed(&self, path: &Path, error: io::Error) -> RustgenError {
+        RustgenError::new(
+            ErrorKind::Io,
+            format!(
+                "Could not write {}, all changes have been rolled back: {}",
+                self.relative(path),
+                error
+            ),
+        )
+        .with_source(error)
+    }
+
+    /// All staged files whose content or mode differs from the original
+    fn changed_files(&self) -> impl Iterator<Item = (&PathBuf, &StagedFile)> {
+        self.files.iter().filter(|(_, file)| {
+            file.original.as_ref() != Some(&file.output()) || file.mode_changed()
+        })
+    }
+
+    /// All staged binary files whose content differs from the original
+    fn changed_assets(&self) -> impl Iterator<Item = (&PathBuf, &StagedAsset)> {
+        self.assets
+            .iter()
+            .filter(|(_, asset)| asset.original.as_ref() != Some(&asset.content))
+    }
+
+    /// All staged links which do not exist yet
+    fn changed_links(&self) -> impl Iterator<Item = (&PathBuf, &StagedLink)> {
+        self.links
+            .iter()
+            .filter(|(_, link)| link.original.as_ref() != Some(&link.target))
+    }
+
+    /// Prints a unified diff of every touched file and a summary of created/modified files
+    pub fn print_diff(&self) {
+        let colored = io::stdout().is_terminal();
+
+        if let Err(error) = self.write_diff(&mut io::stdout().lock(), colored) {
+            eprintln!("Could not print the diff: {}", error);
+        }
+    }
+
+    /// Writes the output of [Workspace::print_diff]
+    fn write_diff(&self, out: &mut impl Write, colored: bool) -> io::Result<()> {
+        let mut created = vec![];
+        let mut modified = vec![];
+
+        for (path, file) in self.changed_files() {
+            let display = self.relative(path);
+            let original = file.original.as_deref().unwrap_or_default();
+
+            let old_header = match &file.original {
+                Some(_) => format!("a/{}", display),
+                None => String::from("/dev/null"),
+            };
+            let output = file.output();
+            let diff = TextDiff::from_lines(original, output.as_str());
+            let diff = diff
+                .unified_diff()
+                .context_radius(3)
+                .header(&old_header, &format!("b/{}", display))
+                .to_string();
+
+            for line in diff.lines() {
+                print_diff_line(out, line, colored)?;
+            }
+
+            let display = match file.mode {
+                Some(mode) if file.mode_changed() => format!("{} (mode {:04o})", display, mode),
+                _ => display,
+            };
+
+            match file.original {
+                Some(_) => modified.push(display),
+                None => created.push(display),
+            }
+        }
+
+        for (path, asset) in self.changed_assets() {
+            let display = self.relative(path);
+
+            match asset.original {
+                Some(_) => {
+                    writeln!(out, "Binary files a/{0} and b/{0} differ", display)?;
+                    modified.push(display);
+                }
+                None => {
+                    writeln!(out, "Binary files /dev/null and b/{} differ", display)?;
+                    created.push(display);
+                }
+            }
+        }
+
+        let linked: Vec<String> = self
+            .changed_links()
+            .map(|(path, link)| format!("{} -> {}", self.relative(path), link.target.display()))
+            .collect();
+
+        writeln!(out)?;
+        writeln!(
+            out,
+            "Dry-run: {} file(s) would be created, {} file(s) would be modified",
+            created.len(),
+            modified.len()
+        )?;
+
+        if !linked.is_empty() {
+            writeln!(out, "Dry-run: {} symlink(s) would be created", linked.len())?;
+        }
+
+        for path in created {
+            writeln!(out, "    created:  {}", path)?;
+        }
+
+        for path in modified {
+            writeln!(out, "    modified: {}", path)?;
+        }
+
+        for link in linked {
+            writeln!(out, "    linked:   {}", link)?;
+        }
+
+        Ok(())
+    }
+
+    fn relative(&self, path: &Path) -> String {
+        path.strip_prefix(&self.root)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    }
+}
+
+impl StagedFile {
+    /// The content as it is written to the disk
+    fn output(&self) -> String {
+        let mut output = String::new();
+
+        if self.format.bom {
+            output.push(BOM);
+        }
+
+        match self.format.crlf {
+            true => output.push_str(&self.content.replace('\n', "\r\n")),
+            false => output.push_str(&self.content),
+        }
+
+        output
+    }
+
+    fn mode_changed(&self) -> bool {
+        self.mode.is_some() && self.mode != self.original_mode
+    }
+}
+
+impl TextFormat {
+    fn detect(content: &str) -> Self {
+        let crlf = content.matches("\r\n").count();
+
+        Self {
+            crlf: crlf > content.matches('\n').count() - crlf,
+            bom: content.starts_with(BOM),
+        }
+    }
+
+    /// Removes the BOM and converts CRLF to `\n` line endings (only for CRLF files, so single
+    /// CRLF lines in other files stay untouched)
+    fn normalize(&self, content: &str) -> String {
+        let content = content.strip_prefix(BOM).unwrap_or(content);
+
+        match self.crlf {
+            true => content.replace("\r\n", "\n"),
+            false => content.to_string(),
+        }
+    }
+}
+
+fn read_existing(path: &Path) -> RustgenResult<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Resolves `.` and `..` of the path without touching the file system (the path may not exist)
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+#[cfg(unix)]
+fn read_mode(path: &Path) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path)
+        .ok()
+        .map(|metadata| metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn read_mode(_path: &Path) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn write_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+/// File modes are unix only, other systems keep the permissions
+#[cfg(not(unix))]
+fn write_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, path)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_target: &Path, _path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symlinks are not supported on this system",
+    ))
+}
+
+/// Writes the staged file with its mode
+fn write_staged(
+    path: &Path,
+    file: &StagedFile,
+    created_directories: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    write_file(path, file.output().as_bytes(), created_directories)?;
+
+    match file.mode {
+        Some(mode) => write_mode(path, mode),
+        None => Ok(()),
+    }
+}
+
+/// Creates the symlink, an existing symlink is replaced
+fn create_link(
+    path: &Path,
+    link: &StagedLink,
+    created_directories: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    create_directories(path, created_directories)?;
+
+    if link.original.is_some() {
+        fs::remove_file(path)?;
+    }
+
+    symlink(&link.target, path)
+}
+
+/// Writes the file and records every directory which had to be created for it
+fn write_file(
+    path: &Path,
+    content: &[u8],
+    created_directories: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    create_directories(path, created_directories)?;
+
+    write_atomic(path, content)
+}
+
+/// Creates the parent directories of the path and records every directory which had to be created
+///
+/// The directories are created one level at a time, so the ones created before a failing level
+/// are recorded (and rolled back) as well
+fn create_directories(path: &Path, created_directories: &mut Vec<PathBuf>) -> io::Result<()> {
+    if let Some(directory) = path.parent() {
+        let missing: Vec<&Path> = directory
+            .ancestors()
+            .take_while(|ancestor| !ancestor.as_os_str().is_empty() && !ancestor.exists())
+            .collect();
+
+        for directory in missing.into_iter().rev() {
+            fs::create_dir(directory)?;
+            created_directories.push(directory.to_path_buf());
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the content to a temporary file next to the target and renames it to the target, so
+/// the target is never left half written. The permissions of an existing target are kept and
+/// symlinks are followed
+fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    let path = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => fs::canonicalize(path)?,
+        _ => path.to_path_buf(),
+    };
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temporary = path.with_file_name(format!(".{}.rustgen-{}.tmp", file_name, process::id()));
+    let permissions = fs::metadata(&path)
+        .ok()
+        .map(|metadata| metadata.permissions());
+
+    let result = File::create(&temporary)
+        .and_then(|mut file| {
+            file.write_all(content)?;
+            file.sync_all()
+        })
+        .and_then(|_| match permissions {
+            Some(permissions) => fs::set_permissions(&temporary, permissions),
+            None => Ok(()),
+        })
+        .and_then(|_| fs::rename(&temporary, &path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+
+    result
+}
+
+/// Restores the original state of all written files and links, errors are only reported as the
+/// original error is more important
+fn rollback(
+    written: &[(&PathBuf, &StagedFile)],
+    copied: &[(&PathBuf, &StagedAsset)],
+    linked: &[(&PathBuf, &StagedLink)],
+    created_directories: &[PathBuf],
+) {
+    for (path, link) in linked.iter().rev() {
+        let result = fs::remove_file(path).and_then(|_| match &link.original {
+            Some(original) => symlink(original, path),
+            None => Ok(()),
+        });
+
+        if let Err(error) = result {
+            eprintln!("Could not roll back {}: {}", path.display(), error);
+        }
+    }
+
+    for (path, asset) in copied.iter().rev() {
+        let result = match &asset.original {
+            Some(original) => write_atomic(path, original),
+            None => fs::remove_file(path),
+        };
+
+        if let Err(error) = result {
+            eprintln!("Could not roll back {}: {}", path.display(), error);
+        }
+    }
+
+    for (path, file) in written.iter().rev() {
+        let result = match (&file.original, file.original_mode) {
+            (Some(original), Some(mode)) if file.mode.is_some() => {
+                write_atomic(path, original.as_bytes()).and_then(|_| write_mode(path, mode))
+            }
+            (Some(original), _) => write_atomic(path, original.as_bytes()),
+            (None, _) => fs::remove_file(path),
+        };
+
+        if let Err(error) = result {
+            eprintln!("Could not roll back {}: {}", path.display(), error);
+        }
+    }
+
+    // Deepest directories first (across all files), so parents are empty when they are removed
+    let mut directories: Vec<&PathBuf> = created_directories.iter().collect();
+
+    directories.sort_by_key(|directory| std::cmp::Reverse(directory.components().count()));
+
+    for directory in directories {
+        // Only removes empty directories, so nothing which existed before is deleted
+        let _ = fs::remove_dir(directory);
+    }
+}
+
+fn print_diff_line(out: &mut impl Write, line: &str, colored: bool) -> io::Result<()> {
+    let color = if line.starts_with("+++") || line.starts_with("---") {
+        COLOR_BOLD
+    } else if line.starts_with('+') {
+        COLOR_GREEN
+    } else if line.starts_with('-') {
+        COLOR_RED
+    } else if line.starts_with("@@") {
+        COLOR_CYAN
+    } else {
+        ""
+    };
+
+    match colored && !color.is_empty() {
+        true => writeln!(out, "{}{}{}", color, line, COLOR_RESET),
+        false => writeln!(out, "{}", line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keeps_line_endings_and_bom() {
+        let original = "\u{feff}a\r\nb\r\n";
+        let format = TextFormat::detect(original);
+        let file = StagedFile {
+            original: Some(original.to_string()),
+            content: format.normalize(original) + "c\n",
+            format,
+            mode: None,
+            original_mode: None,
+        };
+
+        assert_eq!(format.normalize(original), "a\nb\n");
+        assert_eq!(file.output(), "\u{feff}a\r\nb\r\nc\r\n");
+    }
+
+    #[test]
+    fn test_keeps_single_crlf_lines_in_lf_files() {
+        let original = "a\r\nb\nc\n";
+        let format = TextFormat::detect(original);
+
+        assert_eq!(format, TextFormat::default());
+        assert_eq!(format.normalize(original), original);
+    }
+
+    #[test]
+    fn test_links_stay_inside_root() {
+        let mut workspace = Workspace::new(PathBuf::from("/project"));
+
+        assert!(workspace.link("bin/tool", "../scripts/tool.sh").is_ok());
+        assert!(workspace.link("bin/./tool", "../../etc/passwd").is_err());
+        assert!(workspace.link("../tool", "project/tool.sh").is_err());
+        assert!(workspace.link("tool", "/etc/passwd").is_err());
+    }
+
+    /// An empty directory in the temp directory, unique per test
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("rustgen-{}-{}", name, process::id()));
+
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+
+        root
+    }
+
+    #[test]
+    fn test_dry_run_prints_diff_without_writing() {
+        let root = temp_root("dry-run");
+        let mut workspace = Workspace::new(root.clone());
+        let mut output = vec![];
+
+        fs::write(root.join("existing.txt"), "a\nold\n").unwrap();
+        workspace
+            .write("existing.txt", String::from("a\nnew\n"))
+            .unwrap();
+        workspace
+            .write("out/new.txt", String::from("new\n"))
+            .unwrap();
+        workspace.write_diff(&mut output, false).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("--- a/existing.txt\n+++ b/existing.txt\n"));
+        assert!(output.contains(" a\n-old\n+new\n"));
+        assert!(output.contains("--- /dev/null\n+++ b/out/new.txt\n"));
+        assert!(output.contains(
+            "Dry-run: 1 file(s) would be created, 1 file(s) would be modified\n    \
+            created:  out/new.txt\n    modified: existing.txt\n"
+        ));
+        assert_eq!(
+            fs::read_to_string(root.join("existing.txt")).unwrap(),
+            "a\nold\n"
+        );
+        assert!(!root.join("out").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_commit_writes_staged_files() {
+        let root = temp_root("commit");
+        let mut workspace = Workspace::new(root.clone());
+
+        fs::write(root.join("existing.txt"), "a\r\n").unwrap();
+        workspace
+            .write("existing.txt", String::from("a\nb\n"))
+            .unwrap();
+        workspace
+            .write("out/new.txt", String::from("new\n"))
+            .unwrap();
+
+        // Nothing is written before the commit
+        assert!(!root.join("out").exists());
+
+        workspace.commit().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(root.join("existing.txt")).unwrap(),
+            "a\r\nb\r\n"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("out/new.txt")).unwrap(),
+            "new\n"
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_failed_commit_rolls_back() {
+        let root = temp_root("rollback");
+        let mut workspace = Workspace::new(root.clone());
+
+        fs::write(root.join("existing.txt"), "old\n").unwrap();
+        workspace
+            .write("existing.txt", String::from("new\n"))
+            .unwrap();
+        workspace
+            .write("out/b/one.txt", String::from("1\n"))
+            .unwrap();
+        workspace
+            .write("out/c/two.txt", String::from("2\n"))
+            .unwrap();
+        workspace
+            .write("zz/three.txt", String::from("3\n"))
+            .unwrap();
+        // A file where a directory is needed, so the last file cannot be written
+        fs::write(root.join("zz"), "").unwrap();
+
+        assert!(workspace.commit().is_err());
+        assert_eq!(
+            fs::read_to_string(root.join("existing.txt")).unwrap(),
+            "old\n"
+        );
+        assert!(!root.join("out").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_partially_created_directories_are_rolled_back() {
+        let root = temp_root("partial-directories");
+        let mut workspace = Workspace::new(root.clone());
+        // The second level cannot be created, its name is too long
+        let path = format!("out/{}/file.txt", "x".repeat(300));
+
+        workspace.write(&path, String::from("1\n")).unwrap();
+
+        assert!(workspace.commit().is_err());
+        assert!(!root.join("out").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,109 +1,1791 @@
-use std::{env, fs};
+use std::ops::Range;
+use std::path::Path;
 
-use crate::rustgen_error::{RustgenResult, RustgenError};
-use crate::template::{ExtendLocation, TemplateHeader, WriteAction, Writer};
+use regex::{Captures, Regex, RegexBuilder};
+
+use crate::console::prompt_conflict;
+use crate::rustgen_error::{ErrorKind, RustgenError, RustgenResult};
+use crate::template::helpers::{CAPTURE_END, CAPTURE_START};
+use crate::template::merge::merge;
+use crate::template::rust_code::RustEditOutcome;
+use crate::template::{
+    ConflictPolicy, DeleteTarget, ExtendLocation, Indent, IndentMode, Marker, MergeFormat,
+    MergeOptions, MissingMarkerPolicy, Occurrence, Offset, Pattern, RegexMarker, Region, RustEdit,
+    SkipCondition, SortedList, TemplateHeader, Workspace, WriteAction, WriteOptions, Writer,
+};
+
+/// The columns of a tab, for converting between tab and space indentation
+const TAB_WIDTH: usize = 4;
+
+impl WriteAction {
+    /// A short, human readable description of the action (e.g. for listing the templates)
+    pub fn describe(&self) -> &'static str {
+        match self {
+            WriteAction::CreateFile => "creates",
+            WriteAction::Append(_) => "appends to",
+            WriteAction::Replace(_) => "replaces in",
+            WriteAction::Delete(_) => "deletes from",
+            WriteAction::Region(_) => "updates a region in",
+            WriteAction::Merge(_) => "merges into",
+            WriteAction::Rust(_) => "edits",
+            WriteAction::Symlink(_) => "links",
+        }
+    }
+}
+
+impl WriteOptions {
+    /// Resolves the policy for an existing file: `--force`, then the template header, then the
+    /// config. A prompt is answered by the user (or fails if not interactive)
+    pub fn conflict_policy(
+        &self,
+        header: Option<ConflictPolicy>,
+        path: &str,
+    ) -> RustgenResult<ConflictPolicy> {
+        if self.force {
+            return Ok(ConflictPolicy::Overwrite);
+        }
+
+        match header.unwrap_or(self.on_conflict) {
+            ConflictPolicy::Prompt if self.interactive => prompt_conflict(path),
+            ConflictPolicy::Prompt => Err(RustgenError::new(
+                ErrorKind::Conflict,
+                format!(
+                    "The file {} already exists and on_conflict is prompt, but rustgen cannot ask \
+                    as it is not running interactively. Use --force to overwrite it or another \
+                    on_conflict (skip, overwrite, new)",
+                    path
+                ),
+            )),
+            policy => Ok(policy),
+        }
+    }
+}
+
+/// The `{path}.new` path for [ConflictPolicy::New], fails if it exists as well (e.g. from an
+/// earlier run) instead of replacing it
+pub(super) fn new_path(workspace: &Workspace, path: &str) -> RustgenResult<String> {
+    let new_path = format!("{}.new", path);
+
+    if workspace.read_bytes(&new_path)?.is_some() || workspace.read_link(&new_path)?.is_some() {
+        return Err(RustgenError::new(
+            ErrorKind::Conflict,
+            format!(
+                "The file {} already exists and {} is left from an earlier run. Merge or remove \
+                it first",
+                path, new_path
+            ),
+        ));
+    }
+
+    Ok(new_path)
+}
+
+impl WriteAction {
+    /// Whether the content can use the capture groups of a regex (see
+    /// [crate::template::CaptureHelper]), they are filled in per match
+    fn has_captures(&self) -> bool {
+        matches!(
+            self,
+            WriteAction::Append(ExtendLocation::AfterRegex(_))
+                | WriteAction::Append(ExtendLocation::BeforeRegex(_))
+                | WriteAction::Replace(Pattern::Regex(_))
+        )
+    }
+}
 
 impl Writer {
-    pub fn new(header: TemplateHeader, rendered_template: String) -> Self {
+    pub fn new(header: TemplateHeader, rendered_template: String, options: WriteOptions) -> Self {
         // Remove trailing line breaks
-        let rendered_template = rendered_template.trim_matches('\n').to_string();
+        let mut rendered_template = rendered_template.trim_matches('\n').to_string();
+
+        if !header.action.has_captures() {
+            rendered_template = expand_captures(&rendered_template, None);
+        }
 
         Self {
             header,
             rendered_template,
+            options,
         }
     }
 
-    pub fn run_action(&self) -> RustgenResult<()> {
+    /// Runs the action of the header, all file operations go through the workspace
+    pub fn run_action(&self, workspace: &mut Workspace) -> RustgenResult<()> {
         match &self.header.action {
-            WriteAction::CreateFile => self.action_create_file()?,
-            WriteAction::Append(location) => self.action_append(location.clone())?,
+            WriteAction::CreateFile => self.action_create_file(workspace)?,
+            WriteAction::Append(location) => self.action_append(workspace, location.clone())?,
+            WriteAction::Replace(pattern) => self.action_replace(workspace, pattern)?,
+            WriteAction::Delete(target) => self.action_delete(workspace, target)?,
+            WriteAction::Region(region) => self.action_region(workspace, region)?,
+            WriteAction::Merge(options) => self.action_merge(workspace, options)?,
+            WriteAction::Rust(edit) => self.action_rust(workspace, edit)?,
+            WriteAction::Symlink(target) => self.action_symlink(workspace, target)?,
         }
 
         Ok(())
     }
 
-    fn action_create_file(&self) -> RustgenResult<()> {
-        let cwd = env::current_dir()?;
-        let path = self.header.path.clone();
-        let last_part = path.rfind("/").unwrap_or_default();
-        let dir_path = path.chars().take(last_part).collect::<String>();
+    fn action_create_file(&self, workspace: &mut Workspace) -> RustgenResult<()> {
+        let mut path = self.header.path.clone();
+
+        if workspace.exists(&path)? {
+            match self.conflict_policy(&path)? {
+                ConflictPolicy::Overwrite => {}
+                ConflictPolicy::New => path = new_path(workspace, &path)?,
+                ConflictPolicy::Skip => {
+                    println!("Skipping {}, the file already exists", path);
+
+                    return Ok(());
+                }
+                ConflictPolicy::Fail | ConflictPolicy::Prompt => {
+                    return Err(RustgenError::new(
+                        ErrorKind::Conflict,
+                        format!(
+                            "The file {} already exists. Use --force to overwrite it or set \
+                            on_conflict (skip, overwrite, prompt, new) in the template header",
+                            path
+                        ),
+                    ))
+                }
+            }
+        }
 
-        fs::create_dir_all(cwd.join(dir_path))?;
         // Add linebreak at end of file
-        fs::write(cwd.join(path), &(self.rendered_template.clone() + "\n"))?;
+        workspace.write(&path, self.rendered_template.clone() + "\n")?;
 
-        Ok(())
+        self.apply_mode(workspace, &path)
     }
 
-    fn action_append(&self, location: ExtendLocation) -> RustgenResult<()> {
+    fn action_symlink(&self, workspace: &mut Workspace, target: &str) -> RustgenResult<()> {
+        let mut path = self.header.path.clone();
+        let existing = workspace.read_link(&path)?;
+
+        if existing.as_deref() == Some(Path::new(target)) {
+            println!("Skipping {}, the link already exists", path);
+
+            return Ok(());
+        }
+
+        if existing.is_some() || workspace.exists(&path)? {
+            match self.conflict_policy(&path)? {
+                // Only links are replaced, never the content of a file
+                ConflictPolicy::Overwrite if existing.is_some() => {}
+                ConflictPolicy::New => path = new_path(workspace, &path)?,
+                ConflictPolicy::Skip => {
+                    println!("Skipping {}, the file already exists", path);
+
+                    return Ok(());
+                }
+                _ => {
+                    return Err(RustgenError::new(
+                        ErrorKind::Conflict,
+                        format!(
+                            "Cannot create the link {}, the path already exists. Existing links \
+                            can be replaced with --force or on_conflict (skip, overwrite, new) in \
+                            the template header, files are never replaced",
+                            path
+                        ),
+                    ))
+                }
+            }
+        }
+
+        workspace.link(&path, target)
+    }
+
+    /// Sets the `mode` of the header on the written file
+    fn apply_mode(&self, workspace: &mut Workspace, path: &str) -> RustgenResult<()> {
+        match self.header.mode {
+            Some(mode) => workspace.set_mode(path, mode.0),
+            None => Ok(()),
+        }
+    }
+
+    fn conflict_policy(&self, path: &str) -> RustgenResult<ConflictPolicy> {
+        self.options.conflict_policy(self.header.on_conflict, path)
+    }
+
+    fn action_append(
+        &self,
+        workspace: &mut Workspace,
+        location: ExtendLocation,
+    ) -> RustgenResult<()> {
+        if self.already_applied(&self.get_content(workspace)?)? {
+            println!(
+                "Skipping {}, the content is already present",
+                self.header.path
+            );
+
+            return Ok(());
+        }
+
         match location {
-            ExtendLocation::BeginOfFile => self.append_begin()?,
-            ExtendLocation::EndOfFile => self.append_end()?,
-            ExtendLocation::After(identifier) => self.append_after(identifier)?,
-            ExtendLocation::Before(identifier) => self.append_before(identifier)?,
+            ExtendLocation::BeginOfFile => self.append_begin(workspace)?,
+            ExtendLocation::EndOfFile => self.append_end(workspace)?,
+            ExtendLocation::After(marker) => self.append_at_marker(workspace, &marker, true)?,
+            ExtendLocation::Before(marker) => self.append_at_marker(workspace, &marker, false)?,
+            ExtendLocation::AfterRegex(marker) => self.append_at_regex(workspace, &marker, true)?,
+            ExtendLocation::BeforeRegex(marker) => {
+                self.append_at_regex(workspace, &marker, false)?
+            }
+            ExtendLocation::AtLine(line) => self.append_at_line(workspace, line)?,
+            ExtendLocation::Offset(offset) => self.append_at_offset(workspace, &offset)?,
+            ExtendLocation::Sorted(list) => self.append_sorted(workspace, &list)?,
         }
 
         Ok(())
     }
 
-    fn append_begin(&self) -> RustgenResult<()> {
-        let mut content = self.rendered_template.clone();
-        content.push_str("\n");
-        content.push_str(self.get_content()?.as_str());
+    /// Checks the `skip_if` and `skip_if_present` options of the header against the target content
+    fn already_applied(&self, content: &str) -> RustgenResult<bool> {
+        if self.header.skip_if_present && self.is_present(content) {
+            return Ok(true);
+        }
+
+        match &self.header.skip_if {
+            Some(condition) => condition.is_met(content),
+            None => Ok(false),
+        }
+    }
+
+    /// Whether the content contains the rendered template. With an `indent` the template is
+    /// searched in any indentation, as it is re-indented for the anchor when it is inserted
+    fn is_present(&self, content: &str) -> bool {
+        let template = self.plain_template();
+
+        if self.header.indent.is_none() {
+            return content.contains(&template);
+        }
+
+        let block = reindent(&template, "");
+        let first_line = match block.lines().find(|line| !line.trim().is_empty()) {
+            Some(line) => line,
+            None => return content.contains(&template),
+        };
+        let mut line_start = 0;
 
-        self.write_content(content)?;
+        for line in content.split_inclusive('\n') {
+            // The first line can be indented deeper than the common indentation of the block
+            let indentation = leading_whitespace(line).strip_suffix(leading_whitespace(first_line));
+
+            if let (true, Some(indentation)) = (line.trim() == first_line.trim(), indentation) {
+                let indented = reindent(&block, indentation);
+
+                if content[line_start..].starts_with(indented.trim_start_matches('\n')) {
+                    return true;
+                }
+            }
+
+            line_start += line.len();
+        }
+
+        false
+    }
+
+    fn append_begin(&self, workspace: &mut Workspace) -> RustgenResult<()> {
+        let existing = self.get_content(workspace)?;
+        let mut content = self.indented(&existing, 0..0, false);
+        content.push('\n');
+        content.push_str(existing.as_str());
+
+        self.write_content(workspace, content)?;
 
         Ok(())
     }
 
-    fn append_end(&self) -> RustgenResult<()> {
-        let mut content = self.get_content()?;
-        content.push_str(self.rendered_template.as_str());
-        content.push_str("\n");
+    fn append_end(&self, workspace: &mut Workspace) -> RustgenResult<()> {
+        let mut content = self.get_content(workspace)?;
+        let end = content.len();
+        content.push_str(&self.indented(&content, end..end, true));
+        content.push('\n');
 
-        self.write_content(content)?;
+        self.write_content(workspace, content)?;
 
         Ok(())
     }
 
-    fn append_before(&self, identifier: String) -> RustgenResult<()> {
-        let mut content = self.get_content()?;
+    fn append_at_marker(
+        &self,
+        workspace: &mut Workspace,
+        marker: &Marker,
+        after: bool,
+    ) -> RustgenResult<()> {
+        let content = self.get_content(workspace)?;
+
+        match insert_at_marker(
+            &content,
+            marker,
+            &self.rendered_template,
+            after,
+            self.header.indent.as_ref(),
+        ) {
+            Some(content) => self.write_content(workspace, content),
+            None => self.marker_missing(workspace, &marker.text),
+        }
+    }
+
+    fn append_at_regex(
+        &self,
+        workspace: &mut Workspace,
+        marker: &RegexMarker,
+        after: bool,
+    ) -> RustgenResult<()> {
+        let content = self.get_content(workspace)?;
+
+        match insert_at_regex(
+            &content,
+            marker,
+            &self.rendered_template,
+            after,
+            self.header.indent.as_ref(),
+        )? {
+            Some(content) => self.write_content(workspace, content),
+            None => self.marker_missing(workspace, &marker.pattern),
+        }
+    }
+
+    fn append_at_line(&self, workspace: &mut Workspace, line: i64) -> RustgenResult<()> {
+        let content = self.get_content(workspace)?;
+
+        match insert_at_line(
+            &content,
+            line,
+            &self.rendered_template,
+            self.header.indent.as_ref(),
+        ) {
+            Some(content) => self.write_content(workspace, content),
+            None => self.marker_missing(workspace, &format!("line {}", line)),
+        }
+    }
+
+    fn append_at_offset(&self, workspace: &mut Workspace, offset: &Offset) -> RustgenResult<()> {
+        let content = self.get_content(workspace)?;
+
+        match insert_at_offset(
+            &content,
+            offset,
+            &self.rendered_template,
+            self.header.indent.as_ref(),
+        ) {
+            Some(content) => self.write_content(workspace, content),
+            None => self.marker_missing(workspace, &offset.marker.text),
+        }
+    }
+
+    fn append_sorted(&self, workspace: &mut Workspace, list: &SortedList) -> RustgenResult<()> {
+        let content = self.get_content(workspace)?;
+
+        match insert_sorted(
+            &content,
+            list,
+            &self.rendered_template,
+            self.header.indent.as_ref(),
+        )? {
+            Some(content) => self.write_content(workspace, content),
+            None => self.marker_missing(workspace, &list.marker.text),
+        }
+    }
+
+    fn action_replace(&self, workspace: &mut Workspace, pattern: &Pattern) -> RustgenResult<()> {
+        let content = self.get_content(workspace)?;
+
+        match replace_matches(&content, pattern, &self.rendered_template)? {
+            Some(content) => self.write_content(workspace, content),
+            None => self.marker_missing(workspace, pattern.text()),
+        }
+    }
+
+    fn action_delete(&self, workspace: &mut Workspace, target: &DeleteTarget) -> RustgenResult<()> {
+        let content = self.get_content(workspace)?;
+
+        let result = match target {
+            DeleteTarget::Lines(pattern) => {
+                delete_lines(&content, pattern)?.ok_or_else(|| pattern.text().to_string())
+            }
+            DeleteTarget::Block {
+                begin,
+                end,
+                keep_markers,
+            } => delete_blocks(&content, begin, end, *keep_markers)?,
+        };
+
+        match result {
+            Ok(content) => self.write_content(workspace, content),
+            Err(missing) => self.marker_missing(workspace, &missing),
+        }
+    }
+
+    fn action_region(&self, workspace: &mut Workspace, region: &Region) -> RustgenResult<()> {
+        let content = self.get_content(workspace)?;
+        let begin_found = !region.begin.find(&content).is_empty();
+
+        match replace_regions(&content, region, &self.rendered_template)? {
+            Ok(content) => self.write_content(workspace, content),
+            // Never handled by if_missing: a new region would pair its end marker with the open
+            // begin marker and the next run would replace everything in between
+            Err(missing) if begin_found && missing == region.end => Err(RustgenError::new(
+                ErrorKind::MissingMarker,
+                format!(
+                    "The region in {} starting with {:?} is not closed, the end marker {:?} is \
+                    missing",
+                    self.header.path, region.begin.text, region.end
+                ),
+            )),
+            // The region is created at the end of the file, later runs only replace its content
+            Err(_) if !begin_found && self.header.if_missing == MissingMarkerPolicy::AppendEnd => {
+                let mut content = content;
+
+                if !content.is_empty() && !content.ends_with('\n') {
+                    content.push('\n');
+                }
+
+                for line in [&region.begin.text, &self.rendered_template, &region.end] {
+                    if !line.is_empty() {
+                        content.push_str(line);
+                        content.push('\n');
+                    }
+                }
+
+                self.write_content(workspace, content)
+            }
+            Err(missing) => self.marker_missing(workspace, &missing),
+        }
+    }
+
+    /// The rendered template without captures, re-indented for the anchor if the header sets an
+    /// indentation
+    fn indented(&self, content: &str, anchor: Range<usize>, after: bool) -> String {
+        match &self.header.indent {
+            Some(indent) => reindent(
+                &self.plain_template(),
+                &indent.resolve(content, &anchor, after),
+            ),
+            None => self.plain_template(),
+        }
+    }
+
+    /// The rendered template with empty capture groups, for content which is not at a regex match
+    /// (e.g. appended by `if_missing: append_end`)
+    fn plain_template(&self) -> String {
+        expand_captures(&self.rendered_template, None)
+    }
+
+    fn action_merge(&self, workspace: &mut Workspace, options: &MergeOptions) -> RustgenResult<()> {
+        let format = match options.format {
+            Some(format) => format,
+            None => MergeFormat::from_path(&self.header.path)?,
+        };
+        let target = workspace.read(&self.header.path)?;
+        let content = merge(
+            &self.header.path,
+            target.as_deref(),
+            &self.rendered_template,
+            options.key.as_deref(),
+            format,
+        )?;
+
+        self.write_content(workspace, content)
+    }
+
+    fn action_rust(&self, workspace: &mut Workspace, edit: &RustEdit) -> RustgenResult<()> {
+        let content = self.get_content(workspace)?;
+
+        match edit.apply(
+            &self.header.path,
+            &content,
+            &self.rendered_template,
+            self.header.indent.as_ref(),
+        )? {
+            RustEditOutcome::Edited(content) => self.write_content(workspace, content),
+            RustEditOutcome::Present => {
+                println!(
+                    "Skipping {}, the content is already present",
+                    self.header.path
+                );
+
+                Ok(())
+            }
+            RustEditOutcome::Missing(item) => self.marker_missing(workspace, &item),
+        }
+    }
+
+    /// Handles a marker which is not in the target file, depending on the `if_missing` header
+    fn marker_missing(&self, workspace: &mut Workspace, marker: &str) -> RustgenResult<()> {
+        match self.header.if_missing {
+            MissingMarkerPolicy::Error => Err(RustgenError::new(
+                ErrorKind::MissingMarker,
+                format!(
+                    "The marker {:?} was not found in {}. Set if_missing (error, skip, \
+                    append_end) in the template header to change this behavior",
+                    marker, self.header.path
+                ),
+            )),
+            MissingMarkerPolicy::Skip => {
+                println!(
+                    "Skipping {}, the marker {:?} was not found",
+                    self.header.path, marker
+                );
+
+                Ok(())
+            }
+            MissingMarkerPolicy::AppendEnd => match self.header.action {
+                WriteAction::Delete(_) => Err(RustgenError::new(
+                    ErrorKind::MissingMarker,
+                    format!(
+                        "The marker {:?} was not found in {}. A Delete action cannot use \
+                        if_missing: append_end",
+                        marker, self.header.path
+                    ),
+                )),
+                _ => self.append_end(workspace),
+            },
+        }
+    }
+
+    fn get_content(&self, workspace: &Workspace) -> RustgenResult<String> {
+        workspace.read_existing(&self.header.path)
+    }
+
+    fn write_content(&self, workspace: &mut Workspace, content: String) -> RustgenResult<()> {
+        workspace.write(&self.header.path, content)?;
+
+        self.apply_mode(workspace, &self.header.path)
+    }
+}
+
+impl SkipCondition {
+    pub fn is_met(&self, content: &str) -> RustgenResult<bool> {
+        match self {
+            SkipCondition::Contains(text) => Ok(content.contains(text.as_str())),
+            SkipCondition::Matches { regex } => RegexBuilder::new(regex)
+                .multi_line(true)
+                .build()
+                .map(|regex| regex.is_match(content))
+                .map_err(|error| {
+                    RustgenError::new(
+                        ErrorKind::Config,
+                        format!("Invalid skip_if regex {:?}: {}", regex, error),
+                    )
+                    .with_source(error)
+                }),
+        }
+    }
+}
+
+/// A block in the content, found by [find_blocks]
+struct Block {
+    /// Including the lines of the markers
+    outer: Range<usize>,
+
+    /// Only the lines between the markers
+    inner: Range<usize>,
+}
+
+impl Marker {
+    /// The byte ranges of the selected occurrences
+    fn find(&self, content: &str) -> Vec<Range<usize>> {
+        if self.text.is_empty() {
+            return vec![];
+        }
+
+        let ranges = content
+            .match_indices(&self.text)
+            .map(|(position, text)| position..position + text.len())
+            .collect();
+
+        select_occurrences(ranges, self.occurrence)
+    }
+}
+
+impl RegexMarker {
+    pub fn regex(&self) -> RustgenResult<Regex> {
+        RegexBuilder::new(&self.pattern)
+            .multi_line(self.multiline)
+            .build()
+            .map_err(|error| {
+                RustgenError::new(
+                    ErrorKind::Config,
+                    format!("Invalid marker pattern {:?}: {}", self.pattern, error),
+                )
+                .with_source(error)
+            })
+    }
+
+    /// The byte ranges (of the `marker` group or the whole match) of the selected matches, with
+    /// the groups of the [crate::template::CaptureHelper] filled in the template
+    fn find_expanded(
+        &self,
+        content: &str,
+        template: &str,
+    ) -> RustgenResult<Vec<(Range<usize>, String)>> {
+        let regex = self.regex()?;
+        let matches: Vec<Captures> = regex.captures_iter(content).collect();
+
+        Ok(select_occurrences(matches, self.occurrence)
+            .into_iter()
+            .filter_map(|captures| {
+                let anchor = captures.name("marker").or_else(|| captures.get(0))?;
+
+                Some((anchor.range(), expand_captures(template, Some(&captures))))
+            })
+            .collect())
+    }
+}
+
+impl Indent {
+    /// The indentation for a block inserted after (or before) the anchor
+    fn resolve(&self, content: &str, anchor: &Range<usize>, after: bool) -> String {
+        let marker_line = content[line_start(content, anchor.start)..]
+            .lines()
+            .next()
+            .unwrap_or_default();
+
+        let line = match self {
+            Indent::Spaces(width) => return indent_width(*width, content),
+            Indent::Text(text) => return text.clone(),
+            Indent::Mode(IndentMode::Marker) => marker_line,
+            Indent::Mode(IndentMode::Detect) => {
+                let mut surrounding: Box<dyn Iterator<Item = &str>> = match after {
+                    true => Box::new(content[line_end(content, anchor.end)..].lines()),
+                    false => Box::new(content[..line_start(content, anchor.start)].lines().rev()),
+                };
+
+                surrounding
+                    .find(|line| !line.trim().is_empty())
+                    .unwrap_or(marker_line)
+            }
+        };
+
+        leading_whitespace(line).to_string()
+    }
+}
+
+impl Pattern {
+    /// The searched text or regex, for messages
+    pub fn text(&self) -> &str {
+        match self {
+            Pattern::Text(marker) => &marker.text,
+            Pattern::Regex(marker) => &marker.pattern,
+        }
+    }
+
+    /// The byte ranges of the selected matches, with the capture groups expanded in the template
+    fn find_expanded(
+        &self,
+        content: &str,
+        template: &str,
+    ) -> RustgenResult<Vec<(Range<usize>, String)>> {
+        match self {
+            Pattern::Text(marker) => Ok(marker
+                .find(content)
+                .into_iter()
+                .map(|range| (range, expand_captures(template, None)))
+                .collect()),
+            Pattern::Regex(marker) => marker.find_expanded(content, template),
+        }
+    }
+}
+
+/// Replaces the groups of the [crate::template::CaptureHelper] with the captured text, groups
+/// which did not match (or all groups without captures) become empty
+fn expand_captures(template: &str, captures: Option<&Captures>) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find(CAPTURE_START) {
+        let group_start = start + CAPTURE_START.len_utf8();
+        let group_end = match rest[group_start..].find(CAPTURE_END) {
+            Some(end) => group_start + end,
+            None => break,
+        };
+        let group = &rest[group_start..group_end];
+        let captured = captures.and_then(|captures| match group.parse::<usize>() {
+            Ok(index) => captures.get(index),
+            Err(_) => captures.name(group),
+        });
+
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(captured.map(|capture| capture.as_str()).unwrap_or_default());
+        rest = &rest[group_end + CAPTURE_END.len_utf8()..];
+    }
+
+    expanded.push_str(rest);
+
+    expanded
+}
+
+/// Picks the selected occurrences out of all matches
+fn select_occurrences<T>(mut matches: Vec<T>, occurrence: Occurrence) -> Vec<T> {
+    match occurrence {
+        Occurrence::All => matches,
+        Occurrence::First => matches.into_iter().take(1).collect(),
+        Occurrence::Last => matches.pop().into_iter().collect(),
+        Occurrence::Nth(n) if n >= 1 && n <= matches.len() => vec![matches.swap_remove(n - 1)],
+        Occurrence::Nth(_) => vec![],
+    }
+}
+
+/// Replaces the (ordered, not overlapping) byte ranges with the texts, returns None if there is
+/// nothing to replace
+pub(super) fn splice(content: &str, edits: Vec<(Range<usize>, String)>) -> Option<String> {
+    if edits.is_empty() {
+        return None;
+    }
+
+    let mut content = content.to_string();
+
+    // Editing from the back keeps the ranges in front valid
+    for (range, text) in edits.into_iter().rev() {
+        content.replace_range(range, &text);
+    }
+
+    Some(content)
+}
+
+/// Inserts the block after (or before) the selected occurrences of the marker
+///
+/// Returns None if no occurrence was selected (the marker is missing)
+fn insert_at_marker(
+    content: &str,
+    marker: &Marker,
+    block: &str,
+    after: bool,
+    indent: Option<&Indent>,
+) -> Option<String> {
+    let blocks = marker
+        .find(content)
+        .into_iter()
+        .map(|range| (range, block.to_string()))
+        .collect();
+
+    insert_blocks(content, blocks, after, indent)
+}
+
+/// Inserts the block after (or before) the selected matches of the regex. The groups of the
+/// [crate::template::CaptureHelper] (`{{capture 1}}`, `{{capture "name"}}`) in the block are
+/// filled with the captures of each match
+///
+/// Returns None if no match was selected (the marker is missing)
+fn insert_at_regex(
+    content: &str,
+    marker: &RegexMarker,
+    block: &str,
+    after: bool,
+    indent: Option<&Indent>,
+) -> RustgenResult<Option<String>> {
+    let blocks = marker.find_expanded(content, block)?;
+
+    Ok(insert_blocks(content, blocks, after, indent))
+}
+
+/// Inserts every block after (or before) its anchor, re-indented if an indentation is given
+///
+/// With an indentation, a block before an anchor which only has whitespace in front of it is
+/// inserted as own lines before the line of the anchor
+fn insert_blocks(
+    content: &str,
+    blocks: Vec<(Range<usize>, String)>,
+    after: bool,
+    indent: Option<&Indent>,
+) -> Option<String> {
+    let insertions = blocks
+        .into_iter()
+        .map(|(anchor, block)| {
+            let indentation = indent.map(|indent| indent.resolve(content, &anchor, after));
+            let block = match &indentation {
+                Some(indentation) => reindent(&block, indentation),
+                None => block,
+            };
+            let line_start = line_start(content, anchor.start);
+
+            match after {
+                true => (anchor.end..anchor.end, format!("\n{}", block)),
+                false
+                    if indentation.is_some()
+                        && content[line_start..anchor.start].trim().is_empty() =>
+                {
+                    (line_start..line_start, format!("{}\n", block))
+                }
+                false => (anchor.start..anchor.start, format!("{}\n", block)),
+            }
+        })
+        .collect();
+
+    splice(content, insertions)
+}
+
+/// Inserts the block as the given line, negative lines count from the end
+///
+/// Returns None if the line is outside of the file
+fn insert_at_line(
+    content: &str,
+    line: i64,
+    block: &str,
+    indent: Option<&Indent>,
+) -> Option<String> {
+    let boundaries = line_boundaries(content);
+    let index = match line < 0 {
+        true => boundaries.len() as i64 + line,
+        false => line - 1,
+    };
+
+    if line == 0 || index < 0 || index as usize >= boundaries.len() {
+        return None;
+    }
+
+    insert_lines(content, vec![boundaries[index as usize]], block, indent)
+}
+
+/// Inserts the block at the line offset of every selected occurrence of the marker
+///
+/// Returns None if no occurrence was selected or an insertion point is outside of the file
+fn insert_at_offset(
+    content: &str,
+    offset: &Offset,
+    block: &str,
+    indent: Option<&Indent>,
+) -> Option<String> {
+    let boundaries = line_boundaries(content);
+    // The index of the line containing the position
+    let line_of = |position: usize| boundaries.partition_point(|&start| start <= position) - 1;
+    let mut positions = vec![];
+
+    for range in offset.marker.find(content) {
+        let marker_end = line_of(range.end.saturating_sub(1).max(range.start));
+
+        let index = match offset.before_blank_line {
+            true => (marker_end + 1..boundaries.len() - 1)
+                .find(|&line| {
+                    content[boundaries[line]..boundaries[line + 1]]
+                        .trim()
+                        .is_empty()
+                })
+                .unwrap_or(boundaries.len() - 1) as i64,
+            false => match offset.lines < 0 {
+                true => line_of(range.start) as i64 + offset.lines + 1,
+                false => marker_end as i64 + offset.lines + 1,
+            },
+        };
+
+        if index < 0 || index as usize >= boundaries.len() {
+            return None;
+        }
+
+        positions.push(boundaries[index as usize]);
+    }
+
+    insert_lines(content, positions, block, indent)
+}
+
+/// Inserts the block as own lines at the given line starts
+pub(super) fn insert_lines(
+    content: &str,
+    positions: Vec<usize>,
+    block: &str,
+    indent: Option<&Indent>,
+) -> Option<String> {
+    let insertions = positions
+        .into_iter()
+        .map(|position| {
+            let block = match indent {
+                Some(indent) => reindent(
+                    block,
+                    &indent.resolve(content, &(position..position), false),
+                ),
+                None => block.to_string(),
+            };
+
+            // The last line of the file has no line break
+            match position == content.len() && !content.is_empty() && !content.ends_with('\n') {
+                true => (position..position, format!("\n{}", block)),
+                false => (position..position, format!("{}\n", block)),
+            }
+        })
+        .collect();
+
+    splice(content, insertions)
+}
+
+/// The byte positions of the start of every line and the end of the content
+fn line_boundaries(content: &str) -> Vec<usize> {
+    let mut boundaries: Vec<usize> = std::iter::once(0)
+        .chain(
+            content
+                .match_indices('\n')
+                .map(|(index, _)| index + 1)
+                .filter(|&index| index < content.len()),
+        )
+        .collect();
+
+    if !content.is_empty() {
+        boundaries.push(content.len());
+    }
+
+    boundaries
+}
+
+/// Inserts every line of the block in sorted order (compared without indentation) into the list
+/// following the selected occurrences of the marker, skipping lines which are already in the list.
+/// A list ends at its end pattern or the next occurrence of the marker
+///
+/// Returns None if no occurrence was selected (the marker is missing)
+fn insert_sorted(
+    content: &str,
+    list: &SortedList,
+    block: &str,
+    indent: Option<&Indent>,
+) -> RustgenResult<Option<String>> {
+    let pattern = list.end.as_deref().unwrap_or(r"^\s*$");
+    let end_regex = Regex::new(pattern).map_err(|error| {
+        RustgenError::new(
+            ErrorKind::Config,
+            format!("Invalid end pattern {:?}: {}", pattern, error),
+        )
+        .with_source(error)
+    })?;
+    let mut edits = vec![];
+
+    for range in list.marker.find(content) {
+        let start = line_end(content, range.end);
+        let mut end = start;
+
+        while end < content.len() {
+            let next = line_end(content, end);
+
+            let line = &content[end..next];
+
+            // Without a terminating line the list ends at the next occurrence of the marker
+            if end_regex.is_match(line.trim_end_matches(['\r', '\n']))
+                || line.contains(&list.marker.text)
+            {
+                break;
+            }
+
+            end = next;
+        }
+
+        let block = match indent {
+            Some(indent) => reindent(block, &indent.resolve(content, &range, true)),
+            None => block.to_string(),
+        };
+        let mut lines: Vec<&str> = content[start..end].lines().collect();
+
+        for line in block.lines().filter(|line| !line.trim().is_empty()) {
+            if lines.iter().any(|existing| existing.trim() == line.trim()) {
+                continue;
+            }
+
+            let index = lines
+                .iter()
+                .position(|existing| existing.trim() > line.trim())
+                .unwrap_or(lines.len());
+
+            lines.insert(index, line);
+        }
+
+        let mut sorted = lines.join("\n");
+
+        if !sorted.is_empty() {
+            sorted.push('\n');
+        }
+
+        // The marker is in the last line of the file, which has no line break
+        if start == content.len() && !content.is_empty() && !content.ends_with('\n') {
+            sorted.insert(0, '\n');
+        }
+
+        edits.push((start..end, sorted));
+    }
+
+    Ok(splice(content, edits))
+}
+
+/// Replaces the selected matches of the pattern with the replacement
+///
+/// Returns None if no match was selected (the pattern is missing)
+fn replace_matches(
+    content: &str,
+    pattern: &Pattern,
+    replacement: &str,
+) -> RustgenResult<Option<String>> {
+    Ok(splice(
+        content,
+        pattern.find_expanded(content, replacement)?,
+    ))
+}
+
+/// Deletes every line containing a selected match of the pattern
+///
+/// Returns None if no match was selected (the pattern is missing)
+fn delete_lines(content: &str, pattern: &Pattern) -> RustgenResult<Option<String>> {
+    let mut lines: Vec<Range<usize>> = vec![];
+
+    for (range, _) in pattern.find_expanded(content, "")? {
+        let line = line_start(content, range.start)..line_end(content, range.end);
+
+        // Multiple matches in one line (or overlapping lines) are deleted once
+        match lines.last_mut() {
+            Some(last) if last.end >= line.start => last.end = last.end.max(line.end),
+            _ => lines.push(line),
+        }
+    }
 
-        content = content.replace(
-            identifier.as_str(),
-            format!("{}\n{}", self.rendered_template, identifier).as_str(),
+    Ok(splice(
+        content,
+        lines
+            .into_iter()
+            .map(|line| (line, String::new()))
+            .collect(),
+    ))
+}
+
+/// The lines of the selected begin markers up to the following end marker
+///
+/// Returns the marker which is missing as inner error, a begin marker before the end marker of
+/// the previous one (a nested or unbalanced block) is an error
+fn find_blocks(
+    content: &str,
+    begin: &Marker,
+    end: &str,
+) -> RustgenResult<Result<Vec<Block>, String>> {
+    let ranges = begin.find(content);
+
+    if ranges.is_empty() {
+        return Ok(Err(begin.text.clone()));
+    }
+
+    let mut blocks: Vec<Block> = vec![];
+
+    for range in ranges {
+        let end_position = match end.is_empty() {
+            true => None,
+            false => content[range.end..]
+                .find(end)
+                .map(|index| range.end + index),
+        };
+        let end_position = match end_position {
+            Some(end_position) => end_position,
+            None => return Ok(Err(end.to_string())),
+        };
+        let nested = content[range.end..end_position].contains(&begin.text);
+        let outer = line_start(content, range.start)..line_end(content, end_position + end.len());
+
+        if nested || matches!(blocks.last(), Some(last) if last.outer.end > outer.start) {
+            return Err(RustgenError::new(
+                ErrorKind::MissingMarker,
+                format!(
+                    "The begin marker {:?} in line {} is not closed by {:?} before the next begin                     marker, nested blocks are not supported",
+                    begin.text,
+                    content[..range.start].matches('\n').count() + 1,
+                    end
+                ),
+            ));
+        }
+
+        let inner_start = line_end(content, range.end);
+
+        blocks.push(Block {
+            outer,
+            // Both markers in one line leave nothing in between
+            inner: inner_start..line_start(content, end_position).max(inner_start),
+        });
+    }
+
+    Ok(Ok(blocks))
+}
+
+/// Deletes the block(s) from the selected begin markers to the following end marker
+///
+/// Returns the marker which is missing as inner error
+fn delete_blocks(
+    content: &str,
+    begin: &Marker,
+    end: &str,
+    keep_markers: bool,
+) -> RustgenResult<Result<String, String>> {
+    let blocks = match find_blocks(content, begin, end)? {
+        Ok(blocks) => blocks,
+        Err(missing) => return Ok(Err(missing)),
+    };
+    let edits = blocks
+        .into_iter()
+        .map(|block| match keep_markers {
+            true => (block.inner, String::new()),
+            false => (block.outer, String::new()),
+        })
+        .collect();
+
+    Ok(splice(content, edits).ok_or_else(|| begin.text.clone()))
+}
+
+/// Replaces the lines between the markers of the region(s) with the content
+///
+/// Returns the marker which is missing as inner error
+fn replace_regions(
+    content: &str,
+    region: &Region,
+    block: &str,
+) -> RustgenResult<Result<String, String>> {
+    let block = match block.is_empty() {
+        true => String::new(),
+        false => format!("{}\n", block),
+    };
+    let blocks = match find_blocks(content, &region.begin, &region.end)? {
+        Ok(blocks) => blocks,
+        Err(missing) => return Ok(Err(missing)),
+    };
+
+    // The content would be inserted after the line of both markers, outside of the region
+    if blocks
+        .iter()
+        .any(|found| found.inner.start == found.outer.end)
+    {
+        return Err(RustgenError::new(
+            ErrorKind::MissingMarker,
+            format!(
+                "The begin marker {:?} and the end marker {:?} are in the same line, they have to \
+                be in separate lines around the content of the region",
+                region.begin.text, region.end
+            ),
+        ));
+    }
+
+    let edits = blocks
+        .into_iter()
+        .map(|found| (found.inner, block.clone()))
+        .collect();
+
+    Ok(splice(content, edits).ok_or_else(|| region.begin.text.clone()))
+}
+
+/// The indentation for a number of columns in the style of the content, files indented with tabs
+/// get a tab for every (started) [TAB_WIDTH] columns
+pub(super) fn indent_width(width: usize, content: &str) -> String {
+    let (mut tabs, mut spaces) = (0, 0);
+
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        match line.as_bytes()[0] {
+            b'\t' => tabs += 1,
+            b' ' => spaces += 1,
+            _ => {}
+        }
+    }
+
+    match tabs > spaces {
+        true => "\t".repeat(width.div_ceil(TAB_WIDTH)),
+        false => " ".repeat(width),
+    }
+}
+
+/// Replaces the common indentation of the block with the given one, blank lines stay empty
+///
+/// The indentation inside of the block is converted to the style of the given one: under a tab
+/// indentation every level of spaces (the smallest indentation step of the block) becomes a tab,
+/// under a space indentation every tab becomes [TAB_WIDTH] spaces
+pub(super) fn reindent(block: &str, indentation: &str) -> String {
+    let common = block
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(leading_whitespace)
+        .reduce(|common, whitespace| {
+            let length = common
+                .chars()
+                .zip(whitespace.chars())
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a.len_utf8())
+                .sum();
+
+            &common[..length]
+        })
+        .unwrap_or_default();
+    let relative = |line: &str| leading_whitespace(&line[common.len()..]).to_string();
+    let step = block
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| relative(line).chars().take_while(|&c| c == ' ').count())
+        .filter(|&spaces| spaces > 0)
+        .min()
+        .unwrap_or(TAB_WIDTH);
+
+    block
+        .lines()
+        .map(|line| {
+            if line.trim().is_empty() {
+                return String::new();
+            }
+
+            let inner = relative(line);
+            let inner = match (indentation.contains('\t'), indentation.is_empty()) {
+                (true, _) => {
+                    let spaces = inner.matches(' ').count();
+                    let tabs = inner.matches('\t').count() + spaces / step;
+
+                    format!("{}{}", "\t".repeat(tabs), " ".repeat(spaces % step))
+                }
+                (false, false) => inner.replace('\t', &" ".repeat(TAB_WIDTH)),
+                (false, true) => inner,
+            };
+
+            format!(
+                "{}{}{}",
+                indentation,
+                inner,
+                &line[common.len()..].trim_start()
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+pub(super) fn leading_whitespace(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// The byte position of the start of the line containing the position
+pub(super) fn line_start(content: &str, position: usize) -> usize {
+    content[..position].rfind('\n').map_or(0, |index| index + 1)
+}
+
+/// The byte position after the line break of the line containing the position
+pub(super) fn line_end(content: &str, position: usize) -> usize {
+    content[position..]
+        .find('\n')
+        .map_or(content.len(), |index| position + index + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn capture(group: &str) -> String {
+        format!("{}{}{}", CAPTURE_START, group, CAPTURE_END)
+    }
+
+    const CONTENT: &str = "// imports\nA\n// imports\nB\n// imports\nC\n";
+
+    fn marker(occurrence: Occurrence) -> Marker {
+        Marker {
+            text: String::from("// imports"),
+            occurrence,
+        }
+    }
+
+    #[test]
+    fn test_after_first_occurrence_by_default() {
+        let marker: Marker = serde_yaml::from_str("\"// imports\"").unwrap();
+
+        assert_eq!(
+            insert_at_marker(CONTENT, &marker, "new", true, None).unwrap(),
+            "// imports\nnew\nA\n// imports\nB\n// imports\nC\n"
         );
+    }
 
-        self.write_content(content)?;
+    #[test]
+    fn test_before_last_occurrence() {
+        assert_eq!(
+            insert_at_marker(CONTENT, &marker(Occurrence::Last), "new", false, None).unwrap(),
+            "// imports\nA\n// imports\nB\nnew\n// imports\nC\n"
+        );
+    }
 
-        Ok(())
+    #[test]
+    fn test_after_nth_occurrence() {
+        assert_eq!(
+            insert_at_marker(CONTENT, &marker(Occurrence::Nth(2)), "new", true, None).unwrap(),
+            "// imports\nA\n// imports\nnew\nB\n// imports\nC\n"
+        );
     }
 
-    fn append_after(&self, identifier: String) -> RustgenResult<()> {
-        let mut content = self.get_content()?;
+    #[test]
+    fn test_after_all_occurrences() {
+        assert_eq!(
+            insert_at_marker(CONTENT, &marker(Occurrence::All), "new", true, None).unwrap(),
+            "// imports\nnew\nA\n// imports\nnew\nB\n// imports\nnew\nC\n"
+        );
+    }
 
-        content = content.replace(
-            identifier.as_str(),
-            format!("{}\n{}", identifier, self.rendered_template).as_str(),
+    #[test]
+    fn test_missing_occurrence() {
+        assert_eq!(
+            insert_at_marker(CONTENT, &marker(Occurrence::Nth(4)), "new", true, None),
+            None
+        );
+        assert_eq!(
+            insert_at_marker("no marker", &marker(Occurrence::First), "new", true, None),
+            None
         );
+    }
 
-        self.write_content(content)?;
+    #[test]
+    fn test_occurrence_from_yaml() {
+        let marker: Marker =
+            serde_yaml::from_str("marker: \"// imports\"\noccurrence:\n  nth: 3").unwrap();
 
-        Ok(())
+        assert_eq!(marker, self::marker(Occurrence::Nth(3)));
     }
 
-    fn get_content(&self) -> RustgenResult<String> {
-        let cwd = env::current_dir()?;
-        let path = self.header.path.clone();
+    #[test]
+    fn test_after_last_regex_match() {
+        let marker: RegexMarker =
+            serde_yaml::from_str("pattern: \"^use .*;$\"\nmultiline: true\noccurrence: last")
+                .unwrap();
 
-        Ok(fs::read_to_string(cwd.join(path.clone())).or(Err(RustgenError::new(format!("Could not open file for append {}", path))))?)
+        assert_eq!(
+            insert_at_regex(
+                "use a;\nuse b;\n\nfn main() {}\n",
+                &marker,
+                "use c;",
+                true,
+                None
+            )
+            .unwrap()
+            .unwrap(),
+            "use a;\nuse b;\nuse c;\n\nfn main() {}\n"
+        );
     }
 
-    fn write_content(&self, content: String) -> RustgenResult<()> {
-        let cwd = env::current_dir()?;
-        fs::write(cwd.join(self.header.path.clone()), &content)?;
+    #[test]
+    fn test_before_regex_marker_group_with_captures() {
+        let marker = RegexMarker {
+            pattern: String::from(r"(?s)impl (?P<type>\w+) \{.*?\n(?P<marker>\})"),
+            multiline: false,
+            occurrence: Occurrence::First,
+        };
 
-        Ok(())
+        assert_eq!(
+            insert_at_regex(
+                "impl Foo {\n    fn a() {}\n}\n",
+                &marker,
+                &format!("    fn new() -> {} {{}}", capture("type")),
+                false,
+                None
+            )
+            .unwrap()
+            .unwrap(),
+            "impl Foo {\n    fn a() {}\n    fn new() -> Foo {}\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_regex_marker_keeps_dollar_signs() {
+        let marker = RegexMarker {
+            pattern: String::from("(?P<marker>// routes)"),
+            multiline: false,
+            occurrence: Occurrence::First,
+        };
+
+        assert_eq!(
+            insert_at_regex(
+                "// routes\n",
+                &marker,
+                "log(`route ${name} costs $5`)",
+                true,
+                None
+            )
+            .unwrap()
+            .unwrap(),
+            "// routes\nlog(`route ${name} costs $5`)\n"
+        );
+    }
+
+    #[test]
+    fn test_regex_marker_missing_appends_without_captures() {
+        let header: TemplateHeader = serde_yaml::from_str(
+            "path: a.rs\naction:\n  Append:\n    AfterRegex:\n      pattern: \"mod (\\\\w+);\"\n\
+            if_missing: append_end\nskip_if_present: true",
+        )
+        .unwrap();
+        let mut workspace = Workspace::new(PathBuf::from("/rustgen-test-missing-root"));
+
+        workspace.write("a.rs", String::from("a\n")).unwrap();
+
+        for _ in 0..2 {
+            Writer::new(
+                header.clone(),
+                format!("// {}generated", capture("1")),
+                WriteOptions::default(),
+            )
+            .run_action(&mut workspace)
+            .unwrap();
+
+            assert_eq!(
+                workspace.read("a.rs").unwrap().unwrap(),
+                "a\n// generated\n"
+            );
+        }
+    }
+
+    #[test]
+    fn test_conflict_policy_precedence() {
+        let options = WriteOptions {
+            on_conflict: ConflictPolicy::New,
+            force: false,
+            interactive: false,
+        };
+        let forced = WriteOptions {
+            force: true,
+            ..options.clone()
+        };
+
+        assert_eq!(
+            forced
+                .conflict_policy(Some(ConflictPolicy::Skip), "a")
+                .unwrap(),
+            ConflictPolicy::Overwrite
+        );
+        assert_eq!(
+            options
+                .conflict_policy(Some(ConflictPolicy::Skip), "a")
+                .unwrap(),
+            ConflictPolicy::Skip
+        );
+        assert_eq!(
+            options.conflict_policy(None, "a").unwrap(),
+            ConflictPolicy::New
+        );
+
+        let error = options
+            .conflict_policy(Some(ConflictPolicy::Prompt), "a")
+            .unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::Conflict);
+        assert!(error.to_string().contains("not running interactively"));
+    }
+
+    #[test]
+    fn test_conflict_new_keeps_earlier_new_file() {
+        let header: TemplateHeader = serde_yaml::from_str("path: a.txt\non_conflict: new").unwrap();
+        let mut workspace = Workspace::new(PathBuf::from("/rustgen-test-missing-root"));
+        let writer = Writer::new(header, String::from("new"), WriteOptions::default());
+
+        workspace.write("a.txt", String::from("old\n")).unwrap();
+        writer.run_action(&mut workspace).unwrap();
+
+        assert_eq!(workspace.read("a.txt.new").unwrap().unwrap(), "new\n");
+
+        workspace
+            .write("a.txt.new", String::from("edited\n"))
+            .unwrap();
+
+        let error = writer.run_action(&mut workspace).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::Conflict);
+        assert_eq!(workspace.read("a.txt.new").unwrap().unwrap(), "edited\n");
+    }
+
+    #[test]
+    fn test_missing_marker_policies() {
+        let expected = [
+            ("error", None),
+            ("skip", Some("a\n")),
+            ("append_end", Some("a\nnew\n")),
+        ];
+
+        for (policy, expected) in expected {
+            let header: TemplateHeader = serde_yaml::from_str(&format!(
+                "path: a.rs\naction:\n  Append:\n    After: \"// marker\"\nif_missing: {}",
+                policy
+            ))
+            .unwrap();
+            let mut workspace = Workspace::new(PathBuf::from("/rustgen-test-missing-root"));
+
+            workspace.write("a.rs", String::from("a\n")).unwrap();
+
+            let result = Writer::new(header, String::from("new"), WriteOptions::default())
+                .run_action(&mut workspace);
+
+            match expected {
+                Some(expected) => {
+                    result.unwrap();
+                    assert_eq!(workspace.read("a.rs").unwrap().unwrap(), expected);
+                }
+                None => {
+                    assert_eq!(result.unwrap_err().kind(), ErrorKind::MissingMarker);
+                    assert_eq!(workspace.read("a.rs").unwrap().unwrap(), "a\n");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_skip_condition() {
+        let contains: SkipCondition = serde_yaml::from_str("\"mod foo;\"").unwrap();
+        let matches: SkipCondition = serde_yaml::from_str("regex: \"^mod foo;$\"").unwrap();
+
+        assert!(contains.is_met("mod bar;\nmod foo;\n").unwrap());
+        assert!(matches.is_met("mod bar;\nmod foo;\n").unwrap());
+        assert!(!matches.is_met("mod bar;\npub mod foo;\n").unwrap());
+    }
+
+    #[test]
+    fn test_replace_all_with_captures() {
+        let pattern: Pattern =
+            serde_yaml::from_str("Regex:\n  pattern: \"(\\\\w+) = 1\"\n  occurrence: all").unwrap();
+
+        assert_eq!(
+            replace_matches(
+                "a = 1\nb = 2\nc = 1\n",
+                &pattern,
+                &format!("{} = 3", capture("1"))
+            )
+            .unwrap()
+            .unwrap(),
+            "a = 3\nb = 2\nc = 3\n"
+        );
+    }
+
+    #[test]
+    fn test_delete_lines() {
+        let pattern = Pattern::Text(marker(Occurrence::All));
+
+        assert_eq!(
+            delete_lines(CONTENT, &pattern).unwrap().unwrap(),
+            "A\nB\nC\n"
+        );
+    }
+
+    #[test]
+    fn test_delete_block() {
+        let content = "a\n// begin\nb\nc\n// end\nd\n";
+        let begin: Marker = serde_yaml::from_str("\"// begin\"").unwrap();
+
+        assert_eq!(
+            delete_blocks(content, &begin, "// end", false).unwrap(),
+            Ok(String::from("a\nd\n"))
+        );
+        assert_eq!(
+            delete_blocks(content, &begin, "// end", true).unwrap(),
+            Ok(String::from("a\n// begin\n// end\nd\n"))
+        );
+        assert_eq!(
+            delete_blocks(content, &begin, "// missing", false).unwrap(),
+            Err(String::from("// missing"))
+        );
+    }
+
+    #[test]
+    fn test_delete_nested_block() {
+        let content = "// begin a\n// begin b\nx\n// end\ny\n";
+
+        for occurrence in ["first", "all"] {
+            let begin: Marker =
+                serde_yaml::from_str(&format!("marker: \"// begin\"\noccurrence: {}", occurrence))
+                    .unwrap();
+            let error = delete_blocks(content, &begin, "// end", false).unwrap_err();
+
+            assert_eq!(error.kind(), ErrorKind::MissingMarker);
+        }
+    }
+
+    #[test]
+    fn test_replace_region() {
+        let region: Region = serde_yaml::from_str("begin: \"// begin\"\nend: \"// end\"").unwrap();
+
+        assert_eq!(
+            replace_regions("a\n// begin\nold\n// end\nb\n", &region, "new").unwrap(),
+            Ok(String::from("a\n// begin\nnew\n// end\nb\n"))
+        );
+        assert_eq!(
+            replace_regions("a\n// begin\n// end\n", &region, "new\nlines").unwrap(),
+            Ok(String::from("a\n// begin\nnew\nlines\n// end\n"))
+        );
+    }
+
+    #[test]
+    fn test_replace_invalid_region() {
+        let region: Region = serde_yaml::from_str(
+            "begin:\n  marker: \"// begin\"\n  occurrence: all\nend: \"// end\"",
+        )
+        .unwrap();
+        let nested = replace_regions("// begin a\n// begin b\nx\n// end\n", &region, "new");
+        let same_line = replace_regions("a\n// begin // end\nb\n", &region, "new");
+
+        assert_eq!(nested.unwrap_err().kind(), ErrorKind::MissingMarker);
+        assert_eq!(same_line.unwrap_err().kind(), ErrorKind::MissingMarker);
+    }
+
+    #[test]
+    fn test_skip_if_present_with_indent_runs_once() {
+        let header: TemplateHeader = serde_yaml::from_str(
+            "path: a.rs\naction:\n  Append:\n    After: \"// marker\"\nindent: marker\n\
+            skip_if_present: true",
+        )
+        .unwrap();
+        let expected = "fn main() {\n    // marker\n    if a {\n        b();\n    }\n}\n";
+        let mut workspace = Workspace::new(PathBuf::from("/rustgen-test-missing-root"));
+
+        workspace
+            .write("a.rs", String::from("fn main() {\n    // marker\n}\n"))
+            .unwrap();
+
+        for _ in 0..2 {
+            Writer::new(
+                header.clone(),
+                String::from("if a {\n    b();\n}"),
+                WriteOptions::default(),
+            )
+            .run_action(&mut workspace)
+            .unwrap();
+
+            assert_eq!(workspace.read("a.rs").unwrap().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_unterminated_region_is_never_appended() {
+        let header: TemplateHeader = serde_yaml::from_str(
+            "path: a.rs\naction:\n  Region:\n    begin: \"// begin\"\n    end: \"// end\"\n\
+            if_missing: append_end",
+        )
+        .unwrap();
+        let original = "top\n// begin\nhand edited\nkeep me\n";
+        let mut workspace = Workspace::new(PathBuf::from("/rustgen-test-missing-root"));
+
+        workspace.write("a.rs", original.to_string()).unwrap();
+
+        let error = Writer::new(header, String::from("new"), WriteOptions::default())
+            .run_action(&mut workspace)
+            .unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::MissingMarker);
+        assert!(error.to_string().contains("\"// end\""));
+        assert_eq!(workspace.read("a.rs").unwrap().unwrap(), original);
+    }
+
+    #[test]
+    fn test_indentation() {
+        let content = "fn main() {\n\tlet a = 1;\n\t// marker\n}\n";
+        let marker: Marker = serde_yaml::from_str("\"// marker\"").unwrap();
+        let block = "if a {\n    b();\n}";
+
+        assert_eq!(
+            insert_at_marker(content, &marker, block, true, Some(&Indent::Spaces(2))).unwrap(),
+            "fn main() {\n\tlet a = 1;\n\t// marker\n\tif a {\n\t\tb();\n\t}\n}\n"
+        );
+        assert_eq!(
+            insert_at_marker(
+                content,
+                &marker,
+                block,
+                false,
+                Some(&Indent::Mode(IndentMode::Marker))
+            )
+            .unwrap(),
+            "fn main() {\n\tlet a = 1;\n\tif a {\n\t\tb();\n\t}\n\t// marker\n}\n"
+        );
+
+        let closing: Marker = serde_yaml::from_str("marker: \"}\"\noccurrence: last").unwrap();
+        let indent: Indent = serde_yaml::from_str("detect").unwrap();
+
+        assert_eq!(
+            insert_at_marker(
+                "impl A {\n    fn a() {}\n}\n",
+                &closing,
+                "fn b() {}",
+                false,
+                Some(&indent)
+            )
+            .unwrap(),
+            "impl A {\n    fn a() {}\n    fn b() {}\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_reindent_converts_tabs_and_spaces() {
+        assert_eq!(
+            reindent("  if a {\n    b();\n      c\n  }", "\t"),
+            "\tif a {\n\t\tb();\n\t\t\tc\n\t}"
+        );
+        assert_eq!(
+            reindent("if a {\n\tb();\n}", "  "),
+            "  if a {\n      b();\n  }"
+        );
+        assert_eq!(indent_width(4, "a\n  b\n"), "    ");
+        assert_eq!(indent_width(2, "a\n\tb\n"), "\t");
+    }
+
+    #[test]
+    fn test_insert_at_line() {
+        let content = "a\nb\nc\n";
+
+        assert_eq!(
+            insert_at_line(content, 1, "new", None).unwrap(),
+            "new\na\nb\nc\n"
+        );
+        assert_eq!(
+            insert_at_line(content, -1, "new", None).unwrap(),
+            "a\nb\nc\nnew\n"
+        );
+        assert_eq!(
+            insert_at_line(content, -2, "new", None).unwrap(),
+            "a\nb\nnew\nc\n"
+        );
+        assert_eq!(insert_at_line("a\nb", 3, "new", None).unwrap(), "a\nb\nnew");
+        assert_eq!(insert_at_line(content, 5, "new", None), None);
+        assert_eq!(insert_at_line(content, 0, "new", None), None);
+    }
+
+    #[test]
+    fn test_insert_at_offset() {
+        let content = "list = [\n  1,\n  2,\n]\n\nend\n";
+        let offset: Offset = serde_yaml::from_str("marker: \"list\"\nlines: 2").unwrap();
+
+        assert_eq!(
+            insert_at_offset(content, &offset, "  3,", None).unwrap(),
+            "list = [\n  1,\n  2,\n  3,\n]\n\nend\n"
+        );
+
+        let offset: Offset = serde_yaml::from_str("marker: \"list\"\nlines: -1").unwrap();
+
+        assert_eq!(
+            insert_at_offset(content, &offset, "# list", None).unwrap(),
+            "# list\nlist = [\n  1,\n  2,\n]\n\nend\n"
+        );
+
+        let offset: Offset =
+            serde_yaml::from_str("marker: \"list\"\nbefore_blank_line: true").unwrap();
+
+        assert_eq!(
+            insert_at_offset(content, &offset, "after = 1", None).unwrap(),
+            "list = [\n  1,\n  2,\n]\nafter = 1\n\nend\n"
+        );
+    }
+
+    #[test]
+    fn test_insert_sorted() {
+        let content = "pub enum Command {\n    Generate,\n    List,\n}\n";
+        let list: SortedList =
+            serde_yaml::from_str("marker: \"pub enum Command {\"\nend: \"^}\"").unwrap();
+
+        assert_eq!(
+            insert_sorted(content, &list, "Help,\nList,", Some(&Indent::Spaces(4)))
+                .unwrap()
+                .unwrap(),
+            "pub enum Command {\n    Generate,\n    Help,\n    List,\n}\n"
+        );
+
+        let content = "// modules\nmod b;\nmod d;\n\nfn main() {}\n";
+        let list: SortedList = serde_yaml::from_str("marker: \"// modules\"").unwrap();
+
+        assert_eq!(
+            insert_sorted(content, &list, "mod a;\nmod c;\nmod e;", None)
+                .unwrap()
+                .unwrap(),
+            "// modules\nmod a;\nmod b;\nmod c;\nmod d;\nmod e;\n\nfn main() {}\n"
+        );
+    }
+
+    #[test]
+    fn test_insert_sorted_stops_at_next_marker() {
+        let list: SortedList =
+            serde_yaml::from_str("marker:\n  marker: \"// m\"\n  occurrence: all").unwrap();
+
+        assert_eq!(
+            insert_sorted("// m\nb\n// m\nd\n", &list, "c", None)
+                .unwrap()
+                .unwrap(),
+            "// m\nb\nc\n// m\nc\nd\n"
+        );
     }
 }